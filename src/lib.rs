@@ -0,0 +1,1982 @@
+// Copyright 2019 Nikhil Benesch.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write as FmtWrite};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{self, Path};
+
+use cargo::core::compiler::CrateType;
+use cargo::core::dependency::DepKind;
+use cargo::core::manifest::TargetKind;
+use cargo::core::resolver::ResolveBehavior;
+use cargo::core::{Dependency, Edition, GitReference, Manifest, Target};
+use cargo::util::interning::InternedString;
+use cargo::util::OptVersionReq;
+use regex_macro::regex;
+
+/// Reads the manifest at `path` and collects the details that rendering needs
+/// beyond what cargo's [`Manifest`] provides.
+pub fn parse_manifest(path: &Path) -> io::Result<ManifestExtra> {
+    let s = fs::read_to_string(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to read manifest at `{}`: {}", path.display(), e),
+        )
+    })?;
+    parse_manifest_from_str(s, path)
+}
+
+/// Like [`parse_manifest`], but for manifest source that has already been
+/// read. `path` is only used in error messages.
+pub fn parse_manifest_from_str(s: String, path: &Path) -> io::Result<ManifestExtra> {
+    // A comment on the very first line is often a directive for whatever tool
+    // generated the manifest, so it is kept in place rather than being treated
+    // like any other comment.
+    let directive = s
+        .lines()
+        .next()
+        .filter(|line| line.starts_with('#'))
+        .map(|line| line.to_owned());
+
+    let (comments, blank_lines, positions, trailing_comments) = {
+        // WARNING: This is *really* hacky, even by cargo-manifmt standards. We
+        // should use a proper comment-preserving TOML parser here, when one is
+        // ready. See, for example, https://github.com/matklad/tom.
+        let mut comments = HashMap::new();
+        let mut current_table = String::new();
+        let mut current_comment = String::new();
+        let mut trailing_comments = HashMap::new();
+        // Comments separated from the next line by a blank line.
+        let mut floating_comment = String::new();
+        let mut positions = HashMap::new();
+        // Keys with a blank line between them and the previous key.
+        let mut blank_lines = HashSet::new();
+        let mut blank = false;
+        let mut multiline_string = None;
+        for (i, line) in s.lines().enumerate().skip(usize::from(directive.is_some())) {
+            // The lines of a multi-line string are part of a value, whatever
+            // they look like.
+            let in_string = multiline_string.is_some();
+            multiline_string = open_multiline_string(line, multiline_string);
+            if in_string {
+                continue;
+            }
+            let line = line.trim();
+            if line.starts_with("[") && line.ends_with("]") {
+                let header_comment = format!("{}{}", floating_comment, current_comment);
+                current_table = line[1..line.len() - 1].to_owned();
+                positions.insert(current_table.clone(), i);
+                if !header_comment.is_empty() {
+                    comments.insert(current_table.clone(), header_comment);
+                }
+                current_comment.clear();
+                floating_comment.clear();
+                blank = false;
+            } else if line.starts_with("#") {
+                current_comment.push_str(line);
+                current_comment.push('\n');
+            } else if line.is_empty() {
+                blank = true;
+                if !current_comment.is_empty() {
+                    floating_comment.push_str(&current_comment);
+                    floating_comment.push('\n');
+                    current_comment.clear();
+                }
+            } else {
+                let key: String = line
+                    .chars()
+                    .take_while(|ch| ch.is_ascii_alphanumeric() || *ch == '-' || *ch == '_')
+                    .collect();
+                if !key.is_empty() {
+                    let key = format!("{}.{}", current_table, key);
+                    positions.entry(key.clone()).or_insert(i);
+                    if blank {
+                        blank_lines.insert(key.clone());
+                    }
+                    if let Some(comment) = trailing_comment(line) {
+                        trailing_comments.insert(key.clone(), comment.to_owned());
+                    }
+                    comments.insert(key, format!("{}{}", floating_comment, current_comment));
+                }
+                current_comment.clear();
+                floating_comment.clear();
+                blank = false;
+            }
+        }
+        (comments, blank_lines, positions, trailing_comments)
+    };
+
+    let toml: toml::Value = toml::from_str(&s).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to parse manifest at `{}`: {}", path.display(), e),
+        )
+    })?;
+    let package = toml.get("package").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "manifest at `{}` has no `[package]` table; virtual manifests are not formatted",
+                path.display()
+            ),
+        )
+    })?;
+    let get_auto_key = |key| package.get(key).and_then(|v| v.as_bool()).unwrap_or(true);
+    let inherited = package
+        .as_table()
+        .into_iter()
+        .flatten()
+        .filter(|(_, v)| v.get("workspace").and_then(|v| v.as_bool()) == Some(true))
+        .map(|(k, _)| k.clone())
+        .collect();
+    // Dependencies written as tables, keyed like `comments`.
+    let mut dep_specs = HashMap::new();
+    let mut dep_tables = vec![(String::new(), &toml)];
+    if let Some(targets) = toml.get("target").and_then(|v| v.as_table()) {
+        for (platform, table) in targets {
+            dep_tables.push((format!("target.{}.", TomlStr(platform)), table));
+        }
+    }
+    for (prefix, table) in dep_tables {
+        for kind in &["dependencies", "dev-dependencies", "build-dependencies"] {
+            let deps = table
+                .get(kind)
+                .or_else(|| table.get(kind.replace('-', "_")))
+                .and_then(|v| v.as_table());
+            for (name, spec) in deps.into_iter().flatten() {
+                if let Some(spec) = spec.as_table() {
+                    dep_specs.insert(format!("{}{}.{}", prefix, kind, name), spec.clone());
+                }
+            }
+        }
+    }
+
+    Ok(ManifestExtra {
+        autobenches: get_auto_key("autobenches"),
+        autobins: get_auto_key("autobins"),
+        autoexamples: get_auto_key("autoexamples"),
+        autotests: get_auto_key("autotests"),
+        badges: toml.get("badges").and_then(|v| v.as_table()).cloned(),
+        blank_lines,
+        cargo_features: toml
+            .get("cargo-features")
+            .and_then(|v| v.as_array())
+            .cloned(),
+        comments,
+        // Whichever line ending the majority of lines use is kept.
+        crlf: s.matches("\r\n").count() * 2 > s.matches('\n').count(),
+        directive,
+        dep_specs,
+        inherited,
+        lints: toml.get("lints").and_then(|v| v.as_table()).cloned(),
+        metadata: package.get("metadata").and_then(|v| v.as_table()).cloned(),
+        patches: toml.get("patch").and_then(|v| v.as_table()).cloned(),
+        positions,
+        profiles: toml.get("profile").and_then(|v| v.as_table()).cloned(),
+        replacements: toml.get("replace").and_then(|v| v.as_table()).cloned(),
+        source: s,
+        trailing_comments,
+        workspace: toml.get("workspace").and_then(|v| v.as_table()).cloned(),
+    })
+}
+
+// Returns the comment at the end of `line`, if there is one. A `#` inside a
+// string does not start a comment.
+fn trailing_comment(line: &str) -> Option<&str> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if ch == '\\' => escaped = true,
+            Some(q) if ch == q => quote = None,
+            Some(_) => (),
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '#' => return Some(line[i..].trim_end()),
+            None => (),
+        }
+    }
+    None
+}
+
+// Returns the delimiter of the multi-line string that is still open at the
+// end of `line`, given the delimiter of the one open at its start, if any.
+fn open_multiline_string(line: &str, mut open: Option<&'static str>) -> Option<&'static str> {
+    let chars: Vec<char> = line.chars().collect();
+    let starts_with = |i: usize, delim: &str| {
+        delim
+            .chars()
+            .enumerate()
+            .all(|(j, ch)| chars.get(i + j) == Some(&ch))
+    };
+    let mut quote = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        match (open, quote) {
+            (Some("\"\"\""), _) | (_, Some('"')) if ch == '\\' => i += 1,
+            (Some(delim), _) if starts_with(i, delim) => {
+                // Up to two more quotes right before the closing delimiter
+                // belong to the string.
+                while chars.get(i + 3) == Some(&ch) {
+                    i += 1;
+                }
+                i += 2;
+                open = None;
+            }
+            (Some(_), _) => (),
+            (None, Some(q)) if ch == q => quote = None,
+            (None, Some(_)) => (),
+            (None, None) if starts_with(i, "\"\"\"") => {
+                open = Some("\"\"\"");
+                i += 2;
+            }
+            (None, None) if starts_with(i, "'''") => {
+                open = Some("'''");
+                i += 2;
+            }
+            (None, None) if ch == '"' || ch == '\'' => quote = Some(ch),
+            (None, None) if ch == '#' => break,
+            (None, None) => (),
+        }
+        i += 1;
+    }
+    open
+}
+
+/// Returns the formatted form of `manifest` and any warnings about it. See
+/// [`render_manifest`].
+pub fn format_manifest(
+    base: &Path,
+    manifest: &Manifest,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<(String, Vec<String>)> {
+    let mut out = vec![];
+    let warnings = render_manifest(&mut out, base, manifest, extra, opts)?;
+    let out = String::from_utf8(out).expect("rendered manifests are UTF-8");
+    Ok((out, warnings))
+}
+
+/// Writes the formatted form of `manifest` to `w`.
+///
+/// `base` is the package root, used to render paths relative to the manifest,
+/// and `extra` must come from [`parse_manifest`] on the same manifest.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use cargo::core::Workspace;
+/// use cargo::util::config::Config;
+/// use cargo_manifmt::Options;
+///
+/// let config = Config::default()?;
+/// let workspace = Workspace::new("/path/to/Cargo.toml".as_ref(), &config)?;
+/// let package = workspace.current()?;
+/// let extra = cargo_manifmt::parse_manifest(package.manifest_path())?;
+///
+/// let mut out: Vec<u8> = vec![];
+/// let warnings = cargo_manifmt::render_manifest(
+///     &mut out,
+///     package.root(),
+///     package.manifest(),
+///     &extra,
+///     &Options::default(),
+/// )?;
+/// for warning in warnings {
+///     eprintln!("warning: {}", warning);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The output uses the same line endings as the source manifest. Problems
+/// that don't prevent formatting, like a malformed author with
+/// [`Options::normalize_authors`], are returned as warnings rather than
+/// printed.
+pub fn render_manifest<W>(
+    w: &mut W,
+    base: &Path,
+    manifest: &Manifest,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<Vec<String>>
+where
+    W: io::Write,
+{
+    let mut out = vec![];
+    let warnings = render_manifest_lf(&mut out, base, manifest, extra, opts)?;
+    if extra.crlf {
+        let out = String::from_utf8(out).expect("rendered manifests are UTF-8");
+        w.write_all(out.replace('\n', "\r\n").as_bytes())?;
+    } else {
+        w.write_all(&out)?;
+    }
+    Ok(warnings)
+}
+
+fn render_manifest_lf<W>(
+    w: &mut W,
+    base: &Path,
+    manifest: &Manifest,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<Vec<String>>
+where
+    W: io::Write,
+{
+    let mut warnings = vec![];
+    let mut extra = extra.with_comments(opts.preserve_comments);
+    if !opts.preserve_dependency_groups {
+        // A blank line above a commented feature still separates groups of
+        // features.
+        extra.blank_lines.retain(|key| key.starts_with("features."));
+    }
+    let extra = &extra;
+
+    if let Some(Section::Dependencies) = opts.only {
+        let mut deps = vec![];
+        render_dependencies(&mut deps, base, manifest, extra, opts)?;
+        let deps = String::from_utf8(deps).expect("rendered manifests are UTF-8");
+        w.write_all(splice_dependencies(&extra.source, &deps).as_bytes())?;
+        return Ok(warnings);
+    }
+
+    let max_width = opts.max_width;
+    let metadata = manifest.metadata();
+    let check_publish = opts.warn_publish
+        && manifest
+            .publish()
+            .as_ref()
+            .is_none_or(|registries| !registries.is_empty());
+
+    if let Some(directive) = &extra.directive {
+        writeln!(w, "{}", directive)?;
+    }
+    if let Some(cargo_features) = &extra.cargo_features {
+        // Keys above the first table header are keyed with an empty table
+        // name.
+        render_comment(w, extra, ".cargo-features")?;
+        writeln!(
+            w,
+            "{}",
+            array_line("cargo-features", cargo_features, max_width)
+        )?;
+        writeln!(w)?;
+    }
+    render_comment(w, extra, "package")?;
+    writeln!(w, "[package]")?;
+    render_comment(w, extra, "package.name")?;
+    writeln!(w, "name = {}", TomlStr(&manifest.name()))?;
+    if extra.inherited.contains("description") {
+        render_inherited(w, extra, "description")?;
+    } else if let Some(description) = &metadata.description {
+        render_comment(w, extra, "package.description")?;
+        let line = format!("description = {}", TomlStr(description));
+        if opts.multiline_strings && line.len() > max_width {
+            writeln!(
+                w,
+                "description = {}",
+                TomlMultilineStr(description, max_width)
+            )?;
+        } else {
+            writeln!(w, "{}", line)?;
+        }
+    } else if check_publish {
+        warnings.push(format!(
+            "package `{}` can be published but has no `description`",
+            manifest.name()
+        ));
+    }
+    if extra.inherited.contains("version") {
+        render_inherited(w, extra, "version")?;
+    } else {
+        render_comment(w, extra, "package.version")?;
+        writeln!(w, "version = {}", TomlStr(&manifest.version().to_string()))?;
+    }
+    if extra.inherited.contains("authors") {
+        render_inherited(w, extra, "authors")?;
+    } else if !metadata.authors.is_empty() {
+        let mut authors = metadata.authors.clone();
+        if opts.normalize_authors {
+            for author in &mut authors {
+                match normalize_author(author) {
+                    Some(normalized) => *author = normalized,
+                    None => warnings.push(format!(
+                        "package `{}` has an author not in `Name <email>` form: {:?}",
+                        manifest.name(),
+                        author
+                    )),
+                }
+            }
+        }
+        render_comment(w, extra, "package.authors")?;
+        writeln!(w, "{}", array_line("authors", &authors, max_width))?;
+    }
+    if extra.inherited.contains("keywords") {
+        render_inherited(w, extra, "keywords")?;
+    } else if !metadata.keywords.is_empty() {
+        render_comment(w, extra, "package.keywords")?;
+        writeln!(
+            w,
+            "{}",
+            array_line("keywords", &metadata.keywords, max_width)
+        )?;
+    }
+    if extra.inherited.contains("categories") {
+        render_inherited(w, extra, "categories")?;
+    } else if !metadata.categories.is_empty() {
+        render_comment(w, extra, "package.categories")?;
+        writeln!(
+            w,
+            "{}",
+            array_line("categories", &metadata.categories, max_width)
+        )?;
+    }
+    if extra.inherited.contains("license") {
+        render_inherited(w, extra, "license")?;
+    } else if let Some(license) = &metadata.license {
+        let mut license = license.clone();
+        if opts.normalize_license {
+            match normalize_license(&license) {
+                Some(normalized) => license = normalized,
+                None => warnings.push(format!(
+                    "package `{}` has a license that is not an SPDX expression: {:?}",
+                    manifest.name(),
+                    license
+                )),
+            }
+        }
+        render_comment(w, extra, "package.license")?;
+        writeln!(w, "license = {}", TomlStr(license))?;
+    }
+    if extra.inherited.contains("license-file") {
+        render_inherited(w, extra, "license-file")?;
+    } else if let Some(license_file) = &metadata.license_file {
+        render_comment(w, extra, "package.license-file")?;
+        writeln!(w, "license-file = {}", TomlStr(slash_path(license_file)))?;
+    }
+    if check_publish && metadata.license.is_none() && metadata.license_file.is_none() {
+        warnings.push(format!(
+            "package `{}` can be published but has no `license` or `license-file`",
+            manifest.name()
+        ));
+    }
+    if extra.inherited.contains("readme") {
+        render_inherited(w, extra, "readme")?;
+    } else {
+        // cargo picks up the first of these files that exists when `readme`
+        // is absent, so turning that off takes an explicit `false`.
+        let inferred = ["README.md", "README.txt", "README"]
+            .iter()
+            .find(|readme| base.join(readme).is_file());
+        match &metadata.readme {
+            Some(readme) => {
+                let readme = slash_path(readme);
+                if inferred != Some(&readme.as_str()) || opts.preserve_inferred {
+                    render_comment(w, extra, "package.readme")?;
+                    writeln!(w, "readme = {}", TomlStr(readme))?;
+                }
+            }
+            None if inferred.is_some() => {
+                render_comment(w, extra, "package.readme")?;
+                writeln!(w, "readme = false")?;
+            }
+            None => (),
+        }
+    }
+    if extra.inherited.contains("homepage") {
+        render_inherited(w, extra, "homepage")?;
+    } else if let Some(homepage) = &metadata.homepage {
+        render_comment(w, extra, "package.homepage")?;
+        writeln!(w, "homepage = {}", TomlStr(homepage))?;
+    }
+    if extra.inherited.contains("repository") {
+        render_inherited(w, extra, "repository")?;
+    } else if let Some(repository) = &metadata.repository {
+        render_comment(w, extra, "package.repository")?;
+        writeln!(w, "repository = {}", TomlStr(repository))?;
+    }
+    if extra.inherited.contains("documentation") {
+        render_inherited(w, extra, "documentation")?;
+    } else if let Some(documentation) = &metadata.documentation {
+        render_comment(w, extra, "package.documentation")?;
+        writeln!(w, "documentation = {}", TomlStr(documentation))?;
+    }
+    if extra.inherited.contains("exclude") {
+        render_inherited(w, extra, "exclude")?;
+    } else if !manifest.exclude().is_empty() {
+        render_comment(w, extra, "package.exclude")?;
+        writeln!(
+            w,
+            "{}",
+            array_line("exclude", manifest.exclude(), max_width)
+        )?;
+    }
+    if extra.inherited.contains("include") {
+        render_inherited(w, extra, "include")?;
+    } else if !manifest.include().is_empty() {
+        render_comment(w, extra, "package.include")?;
+        writeln!(
+            w,
+            "{}",
+            array_line("include", manifest.include(), max_width)
+        )?;
+    }
+    if let Some(links) = manifest.links() {
+        render_comment(w, extra, "package.links")?;
+        writeln!(w, "links = {}", TomlStr(links))?;
+    }
+    if extra.inherited.contains("edition") {
+        render_inherited(w, extra, "edition")?;
+    } else {
+        render_comment(w, extra, "package.edition")?;
+        writeln!(w, "edition = {}", TomlStr(&manifest.edition().to_string()))?;
+    }
+    if extra.inherited.contains("rust-version") {
+        render_inherited(w, extra, "rust-version")?;
+    } else if let Some(rust_version) = manifest.rust_version() {
+        render_comment(w, extra, "package.rust-version")?;
+        writeln!(w, "rust-version = {}", TomlStr(&rust_version))?;
+    }
+    // A resolver set in `[workspace]` is written back there instead.
+    let default_resolver = if manifest.edition() >= Edition::Edition2021 {
+        ResolveBehavior::V2
+    } else {
+        ResolveBehavior::V1
+    };
+    if let Some(resolver) = manifest.resolve_behavior() {
+        let in_workspace = extra
+            .workspace
+            .as_ref()
+            .is_some_and(|ws| ws.contains_key("resolver"));
+        if (resolver != default_resolver || opts.preserve_inferred) && !in_workspace {
+            render_comment(w, extra, "package.resolver")?;
+            writeln!(w, "resolver = {}", TomlStr(resolver.to_manifest()))?;
+        }
+    }
+    if extra.inherited.contains("publish") {
+        render_inherited(w, extra, "publish")?;
+    } else if let Some(publish) = manifest.publish() {
+        if publish.is_empty() {
+            render_comment(w, extra, "package.publish")?;
+            writeln!(w, "publish = false")?;
+        } else {
+            render_comment(w, extra, "package.publish")?;
+            writeln!(w, "{}", array_line("publish", publish, max_width))?;
+        }
+    }
+    if let Some(default_run) = manifest.default_run() {
+        render_comment(w, extra, "package.default-run")?;
+        writeln!(w, "default-run = {}", TomlStr(default_run))?;
+    }
+    if !extra.autobenches {
+        render_comment(w, extra, "package.autobenches")?;
+        writeln!(w, "autobenches = false")?;
+    }
+    if !extra.autobins {
+        render_comment(w, extra, "package.autobins")?;
+        writeln!(w, "autobins = false")?;
+    }
+    if !extra.autoexamples {
+        render_comment(w, extra, "package.autoexamples")?;
+        writeln!(w, "autoexamples = false")?;
+    }
+    if !extra.autotests {
+        render_comment(w, extra, "package.autotests")?;
+        writeln!(w, "autotests = false")?;
+    }
+
+    let mut lib = None;
+    let mut bins = vec![];
+    let mut examples = vec![];
+    let mut tests = vec![];
+    let mut benches = vec![];
+    let mut custom_build = None;
+    for target in manifest.targets() {
+        match target.kind() {
+            TargetKind::Lib(_) => lib = Some(target),
+            TargetKind::Bin => bins.push(target),
+            TargetKind::Test => tests.push(target),
+            TargetKind::Bench => benches.push(target),
+            TargetKind::ExampleLib(_) => examples.push(target),
+            TargetKind::ExampleBin => examples.push(target),
+            TargetKind::CustomBuild => custom_build = Some(target),
+        }
+    }
+
+    // A `metabuild` build script has no path.
+    if let Some(path) = custom_build.and_then(|target| target.src_path().path()) {
+        let path = rel_path(base, path);
+        if path != "build.rs" || opts.preserve_inferred {
+            render_comment(w, extra, "package.build")?;
+            writeln!(w, "build = {}", TomlStr(path))?;
+        }
+    }
+
+    if let Some(metadata) = &extra.metadata {
+        render_metadata(w, "package.metadata", metadata, extra, max_width)?;
+    }
+
+    if let Some(badges) = &extra.badges {
+        render_metadata(w, "badges", badges, extra, max_width)?;
+    }
+
+    if let Some(lib) = lib {
+        render_target(w, base, manifest, extra, opts, lib)?;
+    }
+
+    for bin in bins {
+        render_target(w, base, manifest, extra, opts, bin)?;
+    }
+
+    for example in examples {
+        render_target(w, base, manifest, extra, opts, example)?;
+    }
+
+    for test in tests {
+        render_target(w, base, manifest, extra, opts, test)?;
+    }
+
+    for bench in benches {
+        render_target(w, base, manifest, extra, opts, bench)?;
+    }
+
+    render_dependencies(w, base, manifest, extra, opts)?;
+
+    let mut features = vec![];
+    let summary_features = manifest.summary().features();
+    for (name, specs) in summary_features {
+        let value: Vec<_> = specs.iter().map(|s| s.to_string()).collect();
+        // cargo adds an implicit `name = ["dep:name"]` feature for every
+        // optional dependency that no feature refers to with `dep:`. Only
+        // those features can be left for cargo to infer again.
+        let dep_spec = format!("dep:{}", name);
+        let implicit = value.len() == 1
+            && value[0] == dep_spec
+            && !summary_features.iter().any(|(other, specs)| {
+                other != name && specs.iter().any(|s| s.to_string() == dep_spec)
+            });
+        if !implicit {
+            features.push((name, value));
+        }
+    }
+    if !features.is_empty() {
+        render_header(w, "features", extra)?;
+        for (i, (name, value)) in features.into_iter().enumerate() {
+            let key = format!("features.{}", name);
+            if let Some(comment) = extra.comments.get(&key).filter(|c| !c.is_empty()) {
+                // The comment introduces a group of features, which stays set
+                // apart from the one before it.
+                if i > 0 && extra.blank_lines.contains(&key) {
+                    writeln!(w)?;
+                }
+                write!(w, "{}", comment)?;
+            }
+            let trailing = extra.trailing_comment(&key);
+            writeln!(
+                w,
+                "{}{}",
+                array_line(&TomlKey(name).to_string(), &value, max_width),
+                trailing
+            )?;
+        }
+    }
+
+    if let Some(lints) = &extra.lints {
+        render_lints(w, "lints", lints, extra, opts)?;
+    }
+
+    if let Some(workspace) = &extra.workspace {
+        let default_resolver = default_resolver.to_manifest();
+        render_workspace(w, workspace, &default_resolver, extra, opts)?;
+    }
+
+    if let Some(patches) = &extra.patches {
+        for (registry, deps) in patches {
+            if let toml::Value::Table(deps) = deps {
+                render_raw_table(
+                    w,
+                    &format!("patch.{}", TomlKey(registry)),
+                    deps,
+                    extra,
+                    opts,
+                )?;
+            }
+        }
+    }
+
+    if let Some(replacements) = &extra.replacements {
+        render_raw_table(w, "replace", replacements, extra, opts)?;
+    }
+
+    if let Some(profiles) = &extra.profiles {
+        // Built-in profiles come first, in the order cargo documents them.
+        const BUILTIN: &[&str] = &["dev", "release", "test", "bench"];
+        let mut names: Vec<_> = profiles.keys().collect();
+        names.sort_by_key(|name| {
+            BUILTIN
+                .iter()
+                .position(|b| b == name)
+                .unwrap_or(BUILTIN.len())
+        });
+        for name in names {
+            if let toml::Value::Table(profile) = &profiles[name] {
+                render_profile(w, name, profile, extra, opts)?;
+            }
+        }
+    }
+
+    Ok(warnings)
+}
+
+fn render_dependencies<W>(
+    w: &mut W,
+    base: &Path,
+    manifest: &Manifest,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    // Unconditional tables come first, then each platform's tables, and in
+    // both cases normal, dev, and build dependencies in that order.
+    let mut tables: BTreeMap<_, Vec<&Dependency>> = BTreeMap::new();
+    for dep in manifest.dependencies() {
+        tables
+            .entry((dep.platform(), dep.kind()))
+            .or_default()
+            .push(dep);
+    }
+
+    for (_, mut deps) in tables {
+        let table = dependency_table(deps[0]);
+        deps.sort_by_key(|dep| dep.name_in_toml());
+        // Dependencies whose inline table would be too wide are written as
+        // tables of their own, which must follow every other dependency.
+        let mut inline = vec![];
+        let mut expanded = vec![];
+        let mut first = true;
+        for dep in deps {
+            let key = format!("{}.{}", table, dep.name_in_toml());
+            let entries = dependency_entries(base, dep, extra, opts);
+            let line = if entries.is_empty() {
+                format!(
+                    "{} = {}",
+                    TomlKey(dep.name_in_toml()),
+                    TomlVersion(dep.version_req())
+                )
+            } else {
+                format!(
+                    "{} = {{ {} }}",
+                    TomlKey(dep.name_in_toml()),
+                    entries
+                        .iter()
+                        .map(|(k, v)| format!("{} = {}", k, v))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            };
+            if !entries.is_empty() && line.len() > opts.max_width {
+                expanded.push((dep, key, entries));
+                continue;
+            }
+            if !first && extra.blank_lines.contains(&key) {
+                writeln!(inline)?;
+            }
+            first = false;
+            render_comment(&mut inline, extra, &key)?;
+            writeln!(inline, "{}{}", line, extra.trailing_comment(&key))?;
+        }
+        // The table's own header is left out if every dependency in it has
+        // been expanded, but its comment is kept.
+        if !inline.is_empty() {
+            render_header(w, &table, extra)?;
+            w.write_all(&inline)?;
+        } else {
+            writeln!(w)?;
+            render_comment(w, extra, &table)?;
+        }
+        for (i, (dep, key, entries)) in expanded.into_iter().enumerate() {
+            if !inline.is_empty() || i > 0 {
+                writeln!(w)?;
+            }
+            render_comment(w, extra, &key)?;
+            writeln!(
+                w,
+                "[{}.{}]{}",
+                table,
+                TomlKey(dep.name_in_toml()),
+                extra.trailing_comment(&key)
+            )?;
+            for (k, v) in entries {
+                writeln!(w, "{} = {}", k, v)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Replaces the dependency tables in `source` with `deps`, leaving every other
+// line of the manifest exactly as it was. The rendered tables take the place
+// of the first dependency table in the source.
+fn splice_dependencies(source: &str, deps: &str) -> String {
+    let dep_header_regex = regex!(
+        r#"^\[\s*(target\s*\.\s*("[^"]*"|'[^']*'|[A-Za-z0-9_-]+)\s*\.\s*)?((dev|build)[-_])?dependencies\s*(\..*)?\]$"#
+    );
+
+    // Split the source into chunks that each start at a table header, or at
+    // the comment lines directly above it.
+    let lines: Vec<&str> = source.lines().collect();
+    let mut starts = vec![0];
+    let mut multiline_string = None;
+    for (i, line) in lines.iter().enumerate() {
+        let in_string = multiline_string.is_some();
+        multiline_string = open_multiline_string(line, multiline_string);
+        let line = line.trim();
+        if !in_string && line.starts_with('[') && line.ends_with(']') {
+            let mut start = i;
+            while start > 0 && lines[start - 1].trim_start().starts_with('#') {
+                start -= 1;
+            }
+            starts.push(start.max(*starts.last().unwrap()));
+        }
+    }
+    starts.push(lines.len());
+
+    let mut out = String::new();
+    let mut spliced = false;
+    for chunk in starts.windows(2).map(|w| &lines[w[0]..w[1]]) {
+        let is_deps = chunk
+            .iter()
+            .map(|line| line.trim())
+            .find(|line| line.starts_with('[') && line.ends_with(']'))
+            .is_some_and(|header| dep_header_regex.is_match(header));
+        if !is_deps {
+            for line in chunk {
+                out.push_str(line);
+                out.push('\n');
+            }
+        } else if !spliced {
+            splice_block(&mut out, deps);
+            spliced = true;
+        }
+    }
+    if !spliced {
+        splice_block(&mut out, deps);
+    }
+    trim_blank_lines(&mut out);
+    out
+}
+
+fn splice_block(out: &mut String, block: &str) {
+    if block.is_empty() {
+        return;
+    }
+    trim_blank_lines(out);
+    if out.is_empty() {
+        out.push_str(block.trim_start_matches('\n'));
+    } else {
+        out.push_str(block);
+    }
+    out.push('\n');
+}
+
+fn trim_blank_lines(s: &mut String) {
+    while s.ends_with('\n') {
+        let start = s[..s.len() - 1].rfind('\n').map_or(0, |i| i + 1);
+        if !s[start..].trim().is_empty() {
+            break;
+        }
+        s.truncate(start);
+    }
+}
+
+// Writes the comment captured above `key`, if any.
+fn render_comment<W>(w: &mut W, extra: &ManifestExtra, key: &str) -> io::Result<()>
+where
+    W: io::Write,
+{
+    match extra.comments.get(key) {
+        Some(comment) => write!(w, "{}", comment),
+        None => Ok(()),
+    }
+}
+
+// Writes the `key.workspace = true` that stands in for a package field
+// inherited from the workspace.
+fn render_inherited<W>(w: &mut W, extra: &ManifestExtra, key: &str) -> io::Result<()>
+where
+    W: io::Write,
+{
+    render_comment(w, extra, &format!("package.{}", key))?;
+    writeln!(w, "{}.workspace = true", key)
+}
+
+fn render_header<W>(w: &mut W, table: &str, extra: &ManifestExtra) -> io::Result<()>
+where
+    W: io::Write,
+{
+    writeln!(w)?;
+    render_comment(w, extra, table)?;
+    writeln!(w, "[{}]", table)
+}
+
+fn render_workspace<W>(
+    w: &mut W,
+    workspace: &toml::value::Table,
+    default_resolver: &str,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let max_width = opts.max_width;
+    let mut workspace = workspace.clone();
+    let resolver = workspace.get("resolver").and_then(|v| v.as_str());
+    if resolver == Some(default_resolver) && !opts.preserve_inferred {
+        workspace.remove("resolver");
+    }
+
+    // The header is implied by the subtables, unless there are none and an
+    // empty `[workspace]` is what makes the package a workspace root.
+    if workspace.is_empty() || workspace.values().any(|v| !v.is_table()) {
+        render_header(w, "workspace", extra)?;
+    }
+    for key in &["members", "default-members", "exclude", "resolver"] {
+        match workspace.remove(*key) {
+            Some(toml::Value::Array(array)) => {
+                writeln!(w, "{}", array_line(key, &array, max_width))?
+            }
+            Some(value) => writeln!(w, "{} = {}", key, value)?,
+            None => (),
+        }
+    }
+    for (key, value) in &workspace {
+        if !value.is_table() {
+            writeln!(w, "{} = {}", TomlKey(key), value)?;
+        }
+    }
+    for (key, value) in &workspace {
+        match value {
+            toml::Value::Table(deps) if key == "dependencies" => {
+                render_raw_table(w, "workspace.dependencies", deps, extra, opts)?
+            }
+            toml::Value::Table(lints) if key == "lints" => {
+                render_lints(w, "workspace.lints", lints, extra, opts)?
+            }
+            toml::Value::Table(table) => {
+                render_metadata(w, &format!("workspace.{}", key), table, extra, max_width)?
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn render_profile<W>(
+    w: &mut W,
+    name: &str,
+    profile: &toml::value::Table,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let prefix = format!("profile.{}", TomlKey(name));
+    if opts.preserve_profile_order {
+        return render_metadata(w, &prefix, profile, extra, opts.max_width);
+    }
+
+    // Package overrides are sorted by package name and followed by the
+    // build override, wherever they were written.
+    let mut profile = profile.clone();
+    let packages = profile.remove("package");
+    let build_override = profile.remove("build-override");
+    render_metadata(w, &prefix, &profile, extra, opts.max_width)?;
+    if let Some(toml::Value::Table(packages)) = packages {
+        let mut names: Vec<_> = packages.keys().collect();
+        names.sort();
+        for package in names {
+            if let toml::Value::Table(table) = &packages[package] {
+                let prefix = format!("{}.package.{}", prefix, TomlKey(package));
+                render_metadata(w, &prefix, table, extra, opts.max_width)?;
+            }
+        }
+    }
+    if let Some(toml::Value::Table(table)) = build_override {
+        let prefix = format!("{}.build-override", prefix);
+        render_metadata(w, &prefix, &table, extra, opts.max_width)?;
+    }
+    Ok(())
+}
+
+fn render_lints<W>(
+    w: &mut W,
+    table: &str,
+    lints: &toml::value::Table,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    // Anything other than a tool's lints, like an inherited
+    // `workspace = true`, goes in the top-level table.
+    let (tools, keys): (Vec<_>, Vec<_>) = lints.iter().partition(|(_, v)| v.is_table());
+    if !keys.is_empty() {
+        render_header(w, table, extra)?;
+        for (key, value) in keys {
+            writeln!(w, "{} = {}", TomlKey(key), value)?;
+        }
+    }
+    for (tool, levels) in tools {
+        if let toml::Value::Table(levels) = levels {
+            render_raw_table(
+                w,
+                &format!("{}.{}", table, TomlKey(tool)),
+                levels,
+                extra,
+                opts,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn render_raw_table<W>(
+    w: &mut W,
+    table: &str,
+    deps: &toml::value::Table,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    // Workspace dependencies, patches, and replacements don't map cleanly
+    // back from cargo's `Dependency`s, so they are rendered from the raw
+    // document, one entry per line. Paths are already relative to the
+    // manifest they are written back to. Lint levels are written the same
+    // way.
+    const KEY_ORDER: &[&str] = &[
+        "version",
+        "package",
+        "path",
+        "git",
+        "tag",
+        "branch",
+        "rev",
+        "registry",
+        "registry-index",
+        "default-features",
+        "features",
+        "optional",
+    ];
+
+    render_header(w, table, extra)?;
+    for (name, spec) in deps {
+        let key = format!("{}.{}", table, name);
+        if let Some(comment) = extra.comments.get(&key) {
+            write!(w, "{}", comment)?;
+        }
+        let trailing = extra.trailing_comment(&key);
+        match spec {
+            toml::Value::Table(spec) => {
+                let mut keys: Vec<_> = spec.keys().collect();
+                if opts.dep_key_order == DepKeyOrder::Canonical {
+                    keys.sort_by_key(|k| {
+                        KEY_ORDER
+                            .iter()
+                            .position(|o| o == k)
+                            .unwrap_or(KEY_ORDER.len())
+                    });
+                }
+                writeln!(
+                    w,
+                    "{} = {{ {} }}{}",
+                    TomlKey(name),
+                    keys.iter()
+                        .map(|k| format!("{} = {}", TomlKey(k), spec[*k]))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    trailing
+                )?;
+            }
+            _ => writeln!(w, "{} = {}{}", TomlKey(name), spec, trailing)?,
+        }
+    }
+    Ok(())
+}
+
+fn render_metadata<W>(
+    w: &mut W,
+    key_prefix: &str,
+    metadata: &toml::value::Table,
+    extra: &ManifestExtra,
+    max_width: usize,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let mut non_table_buf = Vec::new();
+    let mut table_buf = Vec::new();
+
+    // Tables that cargo-manifmt doesn't understand keep the order they were
+    // written in. A table without a header of its own sits where its first
+    // subtable does. Keys whose position isn't known, like quoted keys,
+    // follow in alphabetical order.
+    let mut entries: Vec<_> = metadata.iter().collect();
+    entries.sort_by_key(|(key, _)| {
+        let path = format!("{}.{}", key_prefix, TomlKey(key));
+        let subtables = format!("{}.", path);
+        extra
+            .positions
+            .iter()
+            .filter(|(p, _)| **p == path || p.starts_with(&subtables))
+            .map(|(_, i)| *i)
+            .min()
+            .unwrap_or(usize::MAX)
+    });
+
+    for (key, value) in entries {
+        match value {
+            toml::Value::Table(table) => {
+                let new_prefix = format!("{}.{}", key_prefix, TomlKey(key));
+                render_metadata(&mut table_buf, &new_prefix, table, extra, max_width)?;
+            }
+            _ => {
+                let comment_key = format!("{}.{}", key_prefix, key);
+                render_comment(&mut non_table_buf, extra, &comment_key)?;
+                let line = match value {
+                    toml::Value::Array(array) => {
+                        array_line(&TomlKey(key).to_string(), array, max_width)
+                    }
+                    _ => format!("{} = {}", TomlKey(key), value),
+                };
+                let trailing = extra.trailing_comment(&comment_key);
+                writeln!(non_table_buf, "{}{}", line, trailing)?;
+            }
+        }
+    }
+
+    if !non_table_buf.is_empty() {
+        render_header(w, key_prefix, extra)?;
+        w.write_all(&non_table_buf)?;
+    }
+
+    w.write_all(&table_buf)?;
+    Ok(())
+}
+
+// Renders `key = [...]` on one line if it fits within `max_width`, or with
+// one element per line otherwise.
+fn array_line<S>(key: &str, array: &[S], max_width: usize) -> String
+where
+    S: TomlDisplay,
+{
+    let s = format!("{} = {}", key, TomlFlatArray(array));
+    if s.len() > max_width && !array.is_empty() {
+        format!("{} = {}", key, TomlPrettyArray(array))
+    } else {
+        s
+    }
+}
+
+fn render_target<W>(
+    w: &mut W,
+    base: &Path,
+    manifest: &Manifest,
+    extra: &ManifestExtra,
+    opts: &Options,
+    target: &Target,
+) -> io::Result<()>
+where
+    W: io::Write,
+{
+    let max_width = opts.max_width;
+    let mut buf = Vec::new();
+    let path = target.src_path().path().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("target `{}` has no source path", target.name()),
+        )
+    })?;
+    let path = rel_path(base, path);
+    // The paths cargo looks for a target at when none is given. Only the
+    // package's own binary may live in `src/main.rs`.
+    let name = target.name();
+    let candidates = match target.kind() {
+        TargetKind::Lib(_) => vec!["src/lib.rs".to_owned()],
+        TargetKind::Bin if name == manifest.name().as_str() => vec![
+            "src/main.rs".to_owned(),
+            format!("src/bin/{}.rs", name),
+            format!("src/bin/{}/main.rs", name),
+        ],
+        TargetKind::Bin => vec![
+            format!("src/bin/{}.rs", name),
+            format!("src/bin/{}/main.rs", name),
+        ],
+        TargetKind::Test => vec![
+            format!("tests/{}.rs", name),
+            format!("tests/{}/main.rs", name),
+        ],
+        TargetKind::Bench => vec![
+            format!("benches/{}.rs", name),
+            format!("benches/{}/main.rs", name),
+        ],
+        TargetKind::ExampleLib(_) | TargetKind::ExampleBin => vec![
+            format!("examples/{}.rs", name),
+            format!("examples/{}/main.rs", name),
+        ],
+        TargetKind::CustomBuild => vec![],
+    };
+    // cargo refuses to pick between several candidates that exist.
+    let at_std_path = candidates.contains(&path)
+        && candidates
+            .iter()
+            .filter(|candidate| base.join(candidate).exists())
+            .count()
+            == 1;
+    // Without autodiscovery, a target only exists if it has a section.
+    let discovered = match target.kind() {
+        TargetKind::Lib(_) => true,
+        TargetKind::Bin => extra.autobins,
+        TargetKind::Test => extra.autotests,
+        TargetKind::Bench => extra.autobenches,
+        TargetKind::ExampleLib(_) | TargetKind::ExampleBin => extra.autoexamples,
+        TargetKind::CustomBuild => unreachable!(),
+    };
+    let default_name = !target.is_lib() || is_default_lib_name(&manifest.name(), name);
+    if let TargetKind::Lib(crate_types) | TargetKind::ExampleLib(crate_types) = target.kind() {
+        if crate_types.contains(&CrateType::ProcMacro) {
+            writeln!(buf, "proc-macro = true")?;
+        }
+        let mut crate_types: Vec<_> = crate_types
+            .iter()
+            .filter(|ty| **ty != CrateType::ProcMacro)
+            .map(|ty| ty.as_str())
+            .collect();
+        crate_types.sort();
+        // A lib target without an explicit crate type is a plain "lib", but
+        // an example only becomes a library through its crate type.
+        let is_default = match target.kind() {
+            TargetKind::Lib(_) => crate_types.is_empty() || crate_types == ["lib"],
+            _ => false,
+        };
+        if !is_default {
+            writeln!(buf, "{}", array_line("crate-type", &crate_types, max_width))?;
+        }
+    }
+    if !at_std_path || opts.preserve_inferred {
+        writeln!(buf, "path = {}", TomlStr(path))?;
+    }
+    if target.edition() != manifest.edition() {
+        writeln!(buf, "edition = {}", TomlStr(target.edition()))?;
+    }
+    if !target.harness() {
+        writeln!(buf, "harness = false")?;
+    }
+    if !target.documented() && target.is_lib() {
+        writeln!(buf, "doc = false")?;
+    }
+    if let Some(features) = target.required_features().filter(|f| !f.is_empty()) {
+        writeln!(
+            buf,
+            "{}",
+            array_line("required-features", features, max_width)
+        )?;
+    }
+    if !buf.is_empty() || !discovered || !default_name {
+        writeln!(
+            w,
+            "\n[{}]",
+            match target.kind() {
+                TargetKind::Lib(_) => "lib",
+                TargetKind::Bin => "[bin]",
+                TargetKind::Test => "[test]",
+                TargetKind::Bench => "[bench]",
+                TargetKind::ExampleLib(_) | TargetKind::ExampleBin => "[example]",
+                TargetKind::CustomBuild => unreachable!(),
+            }
+        )?;
+        if !(target.is_lib() && default_name) {
+            writeln!(w, "name = {}", TomlStr(name))?;
+        }
+        w.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+// cargo names an inferred lib target after the package, hyphens and all, and
+// only turns them into underscores for the crate name. An explicit name may
+// only be the underscored form.
+fn is_default_lib_name(package_name: &str, target_name: &str) -> bool {
+    target_name == package_name || target_name == package_name.replace('-', "_")
+}
+
+// Returns the keys and values that describe `dep`, or nothing if it is fully
+// described by its version requirement.
+fn dependency_entries(
+    base: &Path,
+    dep: &Dependency,
+    extra: &ManifestExtra,
+    opts: &Options,
+) -> Vec<(String, String)> {
+    let toml_key = format!("{}.{}", dependency_table(dep), dep.name_in_toml());
+    let spec = extra.dep_specs.get(&toml_key);
+    if let Some(spec) =
+        spec.filter(|spec| spec.get("workspace").and_then(|v| v.as_bool()) == Some(true))
+    {
+        // cargo has already merged in the workspace's version and source, so
+        // only the keys set in this manifest are written back.
+        const KEY_ORDER: &[&str] = &["workspace", "default-features", "features", "optional"];
+        let mut keys: Vec<_> = spec.keys().collect();
+        if opts.dep_key_order == DepKeyOrder::Canonical {
+            keys.sort_by_key(|k| {
+                KEY_ORDER
+                    .iter()
+                    .position(|o| o == k)
+                    .unwrap_or(KEY_ORDER.len())
+            });
+        }
+        return keys
+            .into_iter()
+            .map(|k| (TomlKey(k).to_string(), spec[k].to_string()))
+            .collect();
+    }
+    let mut meta: Vec<(&'static str, Box<dyn fmt::Display>)> = vec![];
+    if dep.package_name() != dep.name_in_toml() {
+        meta.push(("package", Box::new(TomlStr(dep.package_name()))));
+    }
+    let source_id = dep.source_id();
+    if source_id.is_path() {
+        let url = source_id.url();
+        meta.push(("path", Box::new(TomlStr(rel_path(base, url.path())))));
+    } else if let Some(git_ref) = source_id.git_reference() {
+        meta.push(("git", Box::new(TomlStr(source_id.url().clone()))));
+        match git_ref {
+            GitReference::Tag(tag) => meta.push(("tag", Box::new(TomlStr(tag)))),
+            // cargo tracks an omitted branch as `DefaultBranch`, so a branch
+            // that is present here was declared explicitly, even if it names
+            // the repository's default.
+            GitReference::Branch(branch) => meta.push(("branch", Box::new(TomlStr(branch)))),
+            GitReference::Rev(rev) => meta.push(("rev", Box::new(TomlStr(rev)))),
+            _ => (),
+        }
+    } else if !source_id.is_default_registry() {
+        // cargo doesn't remember whether an alternative registry was named or
+        // given by its index URL, so whichever the manifest used is kept.
+        for key in ["registry", "registry-index"] {
+            if let Some(registry) = spec.and_then(|spec| spec.get(key)) {
+                meta.push((key, Box::new(registry.clone())));
+            }
+        }
+    }
+    if !dep.uses_default_features() {
+        meta.push(("default-features", Box::new("false")));
+    }
+    if !dep.features().is_empty() {
+        meta.push(("features", Box::new(TomlFlatArray(dep.features()))));
+    }
+    if dep.is_optional() {
+        meta.push(("optional", Box::new("true")));
+    }
+    if meta.is_empty() {
+        return vec![];
+    }
+    // A path or git dependency's version is what gets used once the package
+    // is published, so an explicit one is kept even if it is `*`.
+    let declared = spec.is_some_and(|spec| spec.contains_key("version"));
+    if declared || dep.version_req().to_string() != "*" {
+        meta.insert(0, ("version", Box::new(TomlVersion(dep.version_req()))));
+    }
+    if opts.dep_key_order == DepKeyOrder::Alphabetical {
+        meta.sort_by_key(|(k, _)| *k);
+    }
+    meta.into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_string()))
+        .collect()
+}
+
+// Returns the name of the table that declares `dep`, like `dev-dependencies`
+// or `target."cfg(unix)".dependencies`.
+fn dependency_table(dep: &Dependency) -> String {
+    let kind = match dep.kind() {
+        DepKind::Normal => "dependencies",
+        DepKind::Development => "dev-dependencies",
+        DepKind::Build => "build-dependencies",
+    };
+    match dep.platform() {
+        None => kind.to_owned(),
+        Some(platform) => format!("target.{}.{}", TomlStr(platform), kind),
+    }
+}
+
+// Rewrites an SPDX license expression with `OR` in place of the deprecated
+// `/`, and with the operands of each `AND` and `OR` sorted. Returns `None` if
+// `license` isn't an SPDX expression.
+fn normalize_license(license: &str) -> Option<String> {
+    let token_regex = regex!(r"\(|\)|/|[A-Za-z0-9.+:-]+");
+    let mut tokens = vec![];
+    let mut end = 0;
+    for m in token_regex.find_iter(license) {
+        if !license[end..m.start()].trim().is_empty() {
+            return None;
+        }
+        tokens.push(m.as_str());
+        end = m.end();
+    }
+    if !license[end..].trim().is_empty() {
+        return None;
+    }
+    let mut tokens = tokens.into_iter().peekable();
+    let expr = LicenseExpr::parse(&mut tokens)?;
+    match tokens.next() {
+        None => Some(expr.to_string()),
+        Some(_) => None,
+    }
+}
+
+enum LicenseExpr<'a> {
+    License(&'a str, Option<&'a str>),
+    And(Vec<LicenseExpr<'a>>),
+    Or(Vec<LicenseExpr<'a>>),
+}
+
+impl<'a> LicenseExpr<'a> {
+    // expr := and-expr (("OR" | "/") and-expr)*
+    fn parse<I>(tokens: &mut std::iter::Peekable<I>) -> Option<LicenseExpr<'a>>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut operands = vec![LicenseExpr::parse_and(tokens)?];
+        while let Some(&("OR" | "/")) = tokens.peek() {
+            tokens.next();
+            operands.push(LicenseExpr::parse_and(tokens)?);
+        }
+        Some(LicenseExpr::join(operands, false))
+    }
+
+    // and-expr := primary ("AND" primary)*
+    fn parse_and<I>(tokens: &mut std::iter::Peekable<I>) -> Option<LicenseExpr<'a>>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let mut operands = vec![LicenseExpr::parse_primary(tokens)?];
+        while let Some(&"AND") = tokens.peek() {
+            tokens.next();
+            operands.push(LicenseExpr::parse_primary(tokens)?);
+        }
+        Some(LicenseExpr::join(operands, true))
+    }
+
+    // primary := "(" expr ")" | license ("WITH" exception)?
+    fn parse_primary<I>(tokens: &mut std::iter::Peekable<I>) -> Option<LicenseExpr<'a>>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        match tokens.next()? {
+            "(" => {
+                let expr = LicenseExpr::parse(tokens)?;
+                match tokens.next()? {
+                    ")" => Some(expr),
+                    _ => None,
+                }
+            }
+            ")" | "/" | "AND" | "OR" | "WITH" => None,
+            license => match tokens.peek() {
+                Some(&"WITH") => {
+                    tokens.next();
+                    match tokens.next()? {
+                        ")" | "(" | "/" | "AND" | "OR" | "WITH" => None,
+                        exception => Some(LicenseExpr::License(license, Some(exception))),
+                    }
+                }
+                _ => Some(LicenseExpr::License(license, None)),
+            },
+        }
+    }
+
+    // Combines `operands` with `AND` or `OR`, flattening operands that use
+    // the same operator and sorting the result.
+    fn join(mut operands: Vec<LicenseExpr<'a>>, and: bool) -> LicenseExpr<'a> {
+        if operands.len() == 1 {
+            return operands.pop().unwrap();
+        }
+        let mut flat = vec![];
+        for operand in operands {
+            match operand {
+                LicenseExpr::And(inner) if and => flat.extend(inner),
+                LicenseExpr::Or(inner) if !and => flat.extend(inner),
+                operand => flat.push(operand),
+            }
+        }
+        flat.sort_by_cached_key(|operand| operand.to_string().to_lowercase());
+        if and {
+            LicenseExpr::And(flat)
+        } else {
+            LicenseExpr::Or(flat)
+        }
+    }
+}
+
+impl<'a> fmt::Display for LicenseExpr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (operands, op) = match self {
+            LicenseExpr::License(license, None) => return f.write_str(license),
+            LicenseExpr::License(license, Some(exception)) => {
+                return write!(f, "{} WITH {}", license, exception)
+            }
+            LicenseExpr::And(operands) => (operands, " AND "),
+            LicenseExpr::Or(operands) => (operands, " OR "),
+        };
+        for (i, operand) in operands.iter().enumerate() {
+            if i > 0 {
+                f.write_str(op)?;
+            }
+            match operand {
+                LicenseExpr::License(..) => write!(f, "{}", operand)?,
+                _ => write!(f, "({})", operand)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn normalize_author(author: &str) -> Option<String> {
+    let author_regex = regex!(r#"^(?P<name>[^<>]*?)\s*(<\s*(?P<email>[^<>\s]+@[^<>\s]+)\s*>)?$"#);
+    let caps = author_regex.captures(author.trim())?;
+    let name = caps["name"]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    match caps.name("email") {
+        Some(email) if name.is_empty() => Some(format!("<{}>", email.as_str())),
+        Some(email) => Some(format!("{} <{}>", name, email.as_str())),
+        None if name.is_empty() => None,
+        None => Some(name),
+    }
+}
+
+// Returns `path` relative to `base`. A path with no relative form, like one
+// on another drive, is returned as is.
+fn rel_path(base: &Path, path: impl AsRef<Path>) -> String {
+    let path = path.as_ref();
+    let rel = match (path.components().next(), base.components().next()) {
+        (Some(a), Some(b)) if a != b => None,
+        _ => pathdiff::diff_paths(path, base),
+    };
+    rel.as_deref()
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace(path::MAIN_SEPARATOR, "/")
+}
+
+// Paths written by hand in a manifest may use Windows separators. Relative
+// paths are rewritten with forward slashes, which cargo accepts everywhere.
+fn slash_path(path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        path.to_owned()
+    } else {
+        path.replace('\\', "/")
+    }
+}
+
+/// A part of the manifest that can be formatted on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    /// Every `dependencies`, `dev-dependencies` and `build-dependencies`
+    /// table, including platform-specific ones.
+    Dependencies,
+}
+
+/// Which comments in the source manifest survive formatting.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PreserveComments {
+    /// Remove every comment.
+    None,
+    /// Keep comments on the lines directly above a table header, a
+    /// dependency, or a feature, and comment blocks between features.
+    #[default]
+    Attached,
+    /// Additionally keep other free-floating comments above those entries
+    /// and comments at the end of their lines.
+    All,
+}
+
+/// The order of the keys in a dependency's inline table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DepKeyOrder {
+    /// `version` first, then where the dependency comes from, then how it
+    /// is used.
+    #[default]
+    Canonical,
+    /// Alphabetical order.
+    Alphabetical,
+}
+
+/// Knobs that adjust how manifests are rendered.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Format only this section, leaving the rest of the manifest untouched.
+    pub only: Option<Section>,
+    /// Which comments to keep.
+    pub preserve_comments: PreserveComments,
+    /// How to order the keys of dependencies written as inline tables.
+    pub dep_key_order: DepKeyOrder,
+    /// Normalize whitespace in `authors` entries.
+    pub normalize_authors: bool,
+    /// Rewrite `license` as a canonical SPDX expression.
+    pub normalize_license: bool,
+    /// Warn about publishable packages missing fields crates.io requires.
+    pub warn_publish: bool,
+    /// The longest line an array may occupy before it is split into one
+    /// element per line.
+    pub max_width: usize,
+    /// Write out target paths, `build`, `readme` and `resolver` even when
+    /// they match what cargo would infer.
+    pub preserve_inferred: bool,
+    /// Wrap a `description` that doesn't fit within `max_width` in a
+    /// multi-line string.
+    pub multiline_strings: bool,
+    /// Keep a blank line above each dependency that had one, so that groups
+    /// of dependencies stay apart.
+    pub preserve_dependency_groups: bool,
+    /// Keep profile package overrides and build overrides in the order they
+    /// were written, rather than sorting them.
+    pub preserve_profile_order: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            only: None,
+            preserve_comments: PreserveComments::default(),
+            dep_key_order: DepKeyOrder::default(),
+            normalize_authors: false,
+            normalize_license: false,
+            warn_publish: false,
+            max_width: 100,
+            preserve_inferred: false,
+            multiline_strings: false,
+            preserve_dependency_groups: false,
+            preserve_profile_order: false,
+        }
+    }
+}
+
+impl Options {
+    /// Applies the settings in the `.manifmt.toml` file in `dir`, if there is
+    /// one. Settings the file doesn't mention are left alone.
+    pub fn read_config_file(&mut self, dir: &Path) -> io::Result<()> {
+        let path = dir.join(CONFIG_FILE);
+        let s = match fs::read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let invalid = |msg: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid config file `{}`: {}", path.display(), msg),
+            )
+        };
+        let config: toml::value::Table = toml::from_str(&s).map_err(|e| invalid(e.to_string()))?;
+        for (key, value) in config {
+            match (key.as_str(), value) {
+                ("max_width", toml::Value::Integer(n)) if n >= 0 => self.max_width = n as usize,
+                ("preserve_inferred", toml::Value::Boolean(b)) => self.preserve_inferred = b,
+                ("max_width", _) => {
+                    return Err(invalid("`max_width` must be a non-negative integer".into()))
+                }
+                ("preserve_inferred", _) => {
+                    return Err(invalid("`preserve_inferred` must be a boolean".into()))
+                }
+                (key, _) => return Err(invalid(format!("unknown key `{}`", key))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the contents of a `.manifmt.toml` file holding the settings
+    /// that [`Options::read_config_file`] reads.
+    pub fn config_file(&self) -> String {
+        format!(
+            "max_width = {}\npreserve_inferred = {}\n",
+            self.max_width, self.preserve_inferred
+        )
+    }
+}
+
+/// The name of the file, next to the workspace root manifest, that
+/// [`Options::read_config_file`] reads.
+pub const CONFIG_FILE: &str = ".manifmt.toml";
+
+/// Information about a manifest that cargo does not retain, gathered from the
+/// manifest's source text by [`parse_manifest`].
+#[derive(Clone)]
+pub struct ManifestExtra {
+    autobenches: bool,
+    autobins: bool,
+    autoexamples: bool,
+    autotests: bool,
+    badges: Option<toml::value::Table>,
+    blank_lines: HashSet<String>,
+    cargo_features: Option<toml::value::Array>,
+    comments: HashMap<String, String>,
+    crlf: bool,
+    dep_specs: HashMap<String, toml::value::Table>,
+    directive: Option<String>,
+    inherited: HashSet<String>,
+    lints: Option<toml::value::Table>,
+    metadata: Option<toml::value::Table>,
+    patches: Option<toml::value::Table>,
+    // The line on which each table header or key, keyed like `comments`,
+    // first appears.
+    positions: HashMap<String, usize>,
+    profiles: Option<toml::value::Table>,
+    replacements: Option<toml::value::Table>,
+    source: String,
+    trailing_comments: HashMap<String, String>,
+    workspace: Option<toml::value::Table>,
+}
+
+impl ManifestExtra {
+    // Drops the captured comments that `mode` doesn't want preserved.
+    fn with_comments(&self, mode: PreserveComments) -> ManifestExtra {
+        let mut extra = self.clone();
+        match mode {
+            PreserveComments::None => {
+                extra.comments.clear();
+                extra.directive = None;
+                extra.trailing_comments.clear();
+            }
+            PreserveComments::Attached => {
+                // Free-floating comments are only kept in the features table,
+                // where they typically introduce a group of features.
+                for (key, comment) in &mut extra.comments {
+                    if !key.starts_with("features.") {
+                        if let Some(i) = comment.rfind("\n\n") {
+                            comment.drain(..i + 2);
+                        }
+                    }
+                }
+                extra.trailing_comments.clear();
+            }
+            PreserveComments::All => (),
+        }
+        extra
+    }
+
+    fn trailing_comment(&self, key: &str) -> String {
+        match self.trailing_comments.get(key) {
+            Some(comment) => format!(" {}", comment),
+            None => String::new(),
+        }
+    }
+}
+
+struct TomlStr<S>(S);
+
+impl<S> fmt::Display for TomlStr<S>
+where
+    S: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.to_string().fmt_toml(f)
+    }
+}
+
+// A key, quoted only if it isn't a valid bare key.
+struct TomlKey<S>(S);
+
+impl<S> fmt::Display for TomlKey<S>
+where
+    S: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key = self.0.to_string();
+        let is_bare = !key.is_empty()
+            && key
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_');
+        if is_bare {
+            f.write_str(&key)
+        } else {
+            key.fmt_toml(f)
+        }
+    }
+}
+
+struct TomlFlatArray<'a, S>(&'a [S]);
+
+impl<'a, S> fmt::Display for TomlFlatArray<'a, S>
+where
+    S: TomlDisplay,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_char('[')?;
+        for (i, s) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            s.fmt_toml(f)?;
+        }
+        f.write_char(']')
+    }
+}
+
+struct TomlPrettyArray<'a, S>(&'a [S]);
+
+impl<'a, S> fmt::Display for TomlPrettyArray<'a, S>
+where
+    S: TomlDisplay,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_char('[')?;
+        if !self.0.is_empty() {
+            f.write_char('\n')?;
+        }
+        for s in self.0 {
+            f.write_str("    ")?;
+            s.fmt_toml(f)?;
+            f.write_str(",\n")?;
+        }
+        f.write_char(']')
+    }
+}
+
+// A multi-line basic string, with lines broken after spaces so that they fit
+// within the given width where possible. A backslash at the end of a line
+// trims the newline, so the string's value is unchanged.
+struct TomlMultilineStr<'a>(&'a str, usize);
+
+impl<'a> fmt::Display for TomlMultilineStr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\"\"\"\n")?;
+        let chars: Vec<char> = self.0.chars().collect();
+        let mut width = 0;
+        for (i, &ch) in chars.iter().enumerate() {
+            let next = chars.get(i + 1).copied();
+            match ch {
+                // A break is only safe before a non-whitespace character, as
+                // the line-ending backslash trims all whitespace after it.
+                ' ' if next.is_some_and(|c| !c.is_whitespace()) => {
+                    let word = chars[i + 1..]
+                        .iter()
+                        .take_while(|c| !c.is_whitespace())
+                        .count();
+                    if width > 0 && width + 1 + word + 2 > self.1 {
+                        f.write_str(" \\\n")?;
+                        width = 0;
+                        continue;
+                    }
+                    f.write_char(' ')?;
+                }
+                // Escaping every quote that another quote follows, and the
+                // last character, ensures no run of quotes ends the string.
+                '"' if next.is_none() || next == Some('"') => f.write_str("\\\"")?,
+                '\u{8}' => f.write_str("\\b")?,
+                '\u{9}' => f.write_str("\\t")?,
+                // A raw newline would be rewritten along with the manifest's
+                // line endings, changing the string.
+                '\u{a}' => f.write_str("\\n")?,
+                '\u{c}' => f.write_str("\\f")?,
+                '\u{d}' => f.write_str("\\r")?,
+                '\u{5c}' => f.write_str("\\\\")?,
+                c if c < '\u{1f}' => write!(f, "\\u{:04X}", ch as u32)?,
+                ch => f.write_char(ch)?,
+            }
+            width += 1;
+        }
+        f.write_str("\"\"\"")
+    }
+}
+
+struct TomlVersion<'a>(&'a OptVersionReq);
+
+impl<'a> fmt::Display for TomlVersion<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // A lone caret requirement is written without its operator, as caret
+        // is the default. Everything else is written as declared, modulo the
+        // whitespace that semver normalizes.
+        let s = self.0.to_string();
+        let caret_regex = regex!(r"^\^([^,\s]+)$");
+        match caret_regex.captures(&s) {
+            Some(caps) => write!(f, "{}", TomlStr(&caps[1])),
+            None => write!(f, "{}", TomlStr(s)),
+        }
+    }
+}
+
+trait TomlDisplay {
+    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result;
+}
+
+impl TomlDisplay for toml::Value {
+    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl TomlDisplay for &str {
+    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // A literal string can't hold a quote of its own kind or, apart from
+        // tabs, control characters.
+        let literal = self.contains('"')
+            && !self.contains('\'')
+            && !self.chars().any(|ch| ch.is_control() && ch != '\t');
+        if literal {
+            f.write_char('\'')?;
+            f.write_str(self)?;
+            return f.write_char('\'');
+        }
+
+        f.write_char('\"')?;
+        for ch in self.chars() {
+            match ch {
+                '\u{8}' => f.write_str("\\b")?,
+                '\u{9}' => f.write_str("\\t")?,
+                '\u{a}' => f.write_str("\\n")?,
+                '\u{c}' => f.write_str("\\f")?,
+                '\u{d}' => f.write_str("\\r")?,
+                '\u{22}' => f.write_str("\\\"")?,
+                '\u{5c}' => f.write_str("\\\\")?,
+                c if c < '\u{1f}' => write!(f, "\\u{:04X}", ch as u32)?,
+                ch => f.write_char(ch)?,
+            }
+        }
+        f.write_char('\"')
+    }
+}
+
+impl TomlDisplay for String {
+    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt_toml(f)
+    }
+}
+
+impl TomlDisplay for InternedString {
+    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt_toml(f)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -12,592 +12,638 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::HashSet;
 use std::env;
 use std::error::Error;
-use std::fmt::{self, Write as FmtWrite};
 use std::fs;
-use std::io::{self, Write};
-use std::path::Path;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-use cargo::core::compiler::CrateType;
-use cargo::core::dependency::DepKind;
-use cargo::core::manifest::TargetKind;
-use cargo::core::{Dependency, GitReference, Manifest, Target, Workspace};
+use cargo::core::{find_workspace_root, Edition, EitherManifest, SourceId, Workspace};
 use cargo::util::config::Config;
 use cargo::util::important_paths;
-use cargo::util::interning::InternedString;
-use cargo::util::OptVersionReq;
+use cargo::util::toml::read_manifest_from_str;
+use cargo_manifmt::{
+    parse_manifest_from_str, render_manifest, DepKeyOrder, Options, PreserveComments, Section,
+};
+use cargo_util::paths;
+use clap::{Arg, Command};
+use ignore::WalkBuilder;
 use regex_macro::regex;
+use similar::TextDiff;
 
 fn main() {
-    if let Err(err) = run() {
-        eprintln!("error: {}", err);
+    match run() {
+        Ok(Status::Clean) => (),
+        Ok(Status::Unformatted) => process::exit(1),
+        Err(err) => {
+            print_error(&error_chain(&*err));
+            process::exit(2);
+        }
     }
 }
 
-fn run() -> Result<(), Box<dyn Error>> {
-    let cwd = env::current_dir()?;
-    let root = important_paths::find_root_manifest_for_wd(&cwd)?;
-    let config = Config::default()?;
-    let workspace = Workspace::new(&root, &config)?;
-    for package in workspace.members() {
-        let manifest = package.manifest();
-        let extra = parse_manifest(package.manifest_path())?;
-        let mut out: Vec<u8> = vec![];
-        render_manifest(&mut out, package.root(), manifest, &extra)?;
-        fs::write(package.manifest_path(), out)?;
+// Returns the messages of `err` and each of its causes.
+fn error_chain(err: &dyn Error) -> Vec<String> {
+    let mut chain = vec![err.to_string()];
+    let mut source = err.source();
+    while let Some(err) = source {
+        chain.push(err.to_string());
+        source = err.source();
     }
-    Ok(())
-}
-
-fn parse_manifest(path: &Path) -> io::Result<ManifestExtra> {
-    let s = fs::read_to_string(path)?;
-
-    let comments = {
-        // WARNING: This is *really* hacky, even by cargo-manifmt standards. We
-        // should use a proper comment-preserving TOML parser here, when one is
-        // ready. See, for example, https://github.com/matklad/tom.
-        let mut comments = HashMap::new();
-        let mut current_table = String::new();
-        let mut current_comment = String::new();
-        for line in s.lines() {
-            let line = line.trim();
-            if line.starts_with("[") && line.ends_with("]") {
-                current_table = line[1..line.len() - 1].to_owned();
-            } else if line.starts_with("#") {
-                current_comment.push_str(line);
-                current_comment.push('\n');
-            } else {
-                let key: String = line
-                    .chars()
-                    .take_while(|ch| ch.is_ascii_alphanumeric() || *ch == '-' || *ch == '_')
-                    .collect();
-                if !key.is_empty() {
-                    comments.insert(
-                        format!("{}.{}", current_table, key),
-                        current_comment.clone(),
-                    );
-                }
-                current_comment.clear();
-            }
-        }
-        comments
-    };
-
-    let toml: toml::Value = toml::from_str(&s)?;
-    let package = toml.get("package").unwrap();
-    let get_auto_key = |key| package.get(key).and_then(|v| v.as_bool()).unwrap_or(true);
-    Ok(ManifestExtra {
-        autobenches: get_auto_key("autobenches"),
-        autobins: get_auto_key("autobins"),
-        autoexamples: get_auto_key("autoexamples"),
-        autotests: get_auto_key("autotests"),
-        comments,
-    })
+    chain
 }
 
-fn render_manifest<W>(
-    w: &mut W,
-    base: &Path,
-    manifest: &Manifest,
-    extra: &ManifestExtra,
-) -> io::Result<()>
-where
-    W: io::Write,
-{
-    let metadata = manifest.metadata();
-
-    writeln!(w, "[package]")?;
-    writeln!(w, "name = {}", TomlStr(&manifest.name()))?;
-    if let Some(description) = &metadata.description {
-        writeln!(w, "description = {}", TomlStr(description))?;
-    }
-    writeln!(w, "version = {}", TomlStr(&manifest.version().to_string()))?;
-    if !metadata.authors.is_empty() {
-        writeln!(w, "authors = {}", TomlPrettyArray(&metadata.authors))?;
-    }
-    if !metadata.keywords.is_empty() {
-        writeln!(w, "keywords = {}", TomlPrettyArray(&metadata.keywords))?;
-    }
-    if !metadata.categories.is_empty() {
-        writeln!(w, "categories = {}", TomlPrettyArray(&metadata.categories))?;
-    }
-    if let Some(license) = &metadata.license {
-        writeln!(w, "license = {}", TomlStr(license))?;
-    }
-    if let Some(license_file) = &metadata.license_file {
-        writeln!(w, "license-file = {}", TomlStr(license_file))?;
-    }
-    if let Some(readme) = &metadata.readme {
-        if readme != "README.md" {
-            writeln!(w, "readme = {}", TomlStr(readme))?;
-        }
-    }
-    if let Some(homepage) = &metadata.homepage {
-        writeln!(w, "homepage = {}", TomlStr(homepage))?;
-    }
-    if let Some(repository) = &metadata.repository {
-        writeln!(w, "repository = {}", TomlStr(repository))?;
-    }
-    if let Some(documentation) = &metadata.documentation {
-        writeln!(w, "documentation = {}", TomlStr(documentation))?;
-    }
-    if !manifest.exclude().is_empty() {
-        writeln!(w, "exclude = {}", TomlPrettyArray(manifest.exclude()))?;
-    }
-    if !manifest.include().is_empty() {
-        writeln!(w, "include = {}", TomlPrettyArray(manifest.include()))?;
-    }
-    if let Some(links) = manifest.links() {
-        writeln!(w, "links = {}", TomlStr(links))?;
-    }
-    writeln!(w, "edition = {}", TomlStr(&manifest.edition().to_string()))?;
-    if let Some(rust_version) = manifest.rust_version() {
-        writeln!(w, "rust-version = {}", TomlStr(&rust_version))?;
-    }
-    if let Some(publish) = manifest.publish() {
-        if publish.is_empty() {
-            writeln!(w, "publish = false")?;
-        } else {
-            writeln!(w, "publish = {}", TomlPrettyArray(publish))?;
-        }
-    }
-    if let Some(default_run) = manifest.default_run() {
-        writeln!(w, "default-run = {}", TomlStr(default_run))?;
-    }
-    if !extra.autobenches {
-        writeln!(w, "autobenches = false")?;
-    }
-    if !extra.autobins {
-        writeln!(w, "autobins = false")?;
-    }
-    if !extra.autoexamples {
-        writeln!(w, "autoexamples = false")?;
-    }
-    if !extra.autotests {
-        writeln!(w, "autotests = false")?;
-    }
-
-    let mut lib = None;
-    let mut bins = vec![];
-    let mut examples = vec![];
-    let mut tests = vec![];
-    let mut benches = vec![];
-    let mut custom_build = None;
-    for target in manifest.targets() {
-        match target.kind() {
-            TargetKind::Lib(_) => lib = Some(target),
-            TargetKind::Bin => bins.push(target),
-            TargetKind::Test => tests.push(target),
-            TargetKind::Bench => benches.push(target),
-            TargetKind::ExampleLib(_) => examples.push(target),
-            TargetKind::ExampleBin => examples.push(target),
-            TargetKind::CustomBuild => custom_build = Some(target),
-        }
-    }
-
-    if let Some(custom_build) = custom_build {
-        let path = rel_path(base, custom_build.src_path().path().unwrap());
-        if path != "build.rs" {
-            writeln!(w, "build = {}", TomlStr(path))?;
-        }
-    }
-
-    if let Some(toml::Value::Table(metadata)) = manifest.custom_metadata() {
-        render_metadata(w, "package.metadata", metadata)?;
-    }
-
-    if let Some(lib) = lib {
-        render_target(w, base, &manifest.name(), lib)?;
-    }
-
-    for bin in bins {
-        render_target(w, base, &manifest.name(), bin)?;
-    }
-
-    for example in examples {
-        render_target(w, base, &manifest.name(), example)?;
+fn print_error(chain: &[String]) {
+    eprintln!("error: {}", chain[0]);
+    for cause in &chain[1..] {
+        eprintln!("\nCaused by:\n  {}", cause);
     }
+}
 
-    for test in tests {
-        render_target(w, base, &manifest.name(), test)?;
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
     }
+}
 
-    for bench in benches {
-        render_target(w, base, &manifest.name(), bench)?;
-    }
+// The outcome of a successful run.
+enum Status {
+    // Every manifest is formatted, or has been rewritten.
+    Clean,
+    // In `--check` mode, at least one manifest needs formatting.
+    Unformatted,
+}
 
-    let mut deps: BTreeMap<_, Vec<&Dependency>> = BTreeMap::new();
-    let mut dev_deps = vec![];
-    let mut build_deps = vec![];
-    for dep in manifest.dependencies() {
-        match dep.kind() {
-            DepKind::Normal => {
-                deps.entry(dep.platform()).or_default().push(dep);
-            }
-            DepKind::Development => dev_deps.push(dep),
-            DepKind::Build => build_deps.push(dep),
-        }
-    }
+fn cli() -> Command<'static> {
+    Command::new("cargo")
+        .bin_name("cargo")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("manifmt")
+                .about("rustfmt for your Cargo.toml")
+                .arg(
+                    Arg::new("check")
+                        .long("check")
+                        .help("Report manifests that need formatting instead of rewriting them"),
+                )
+                .arg(
+                    Arg::new("dep-key-order")
+                        .long("dep-key-order")
+                        .value_name("ORDER")
+                        .possible_values(["canonical", "alpha"])
+                        .default_value("canonical")
+                        .help("How to order the keys of inline dependency tables"),
+                )
+                .arg(
+                    Arg::new("diff")
+                        .long("diff")
+                        .conflicts_with_all(&["check", "stdin"])
+                        .help("Print a diff of the changes formatting would make instead of rewriting"),
+                )
+                .arg(
+                    Arg::new("no-color")
+                        .long("no-color")
+                        .requires("diff")
+                        .help("Don't colorize the output of --diff"),
+                )
+                .arg(
+                    Arg::new("only")
+                        .long("only")
+                        .value_name("SECTION")
+                        .possible_values(["dependencies"])
+                        .help("Format only SECTION, leaving the rest of each manifest untouched"),
+                )
+                .arg(
+                    Arg::new("package")
+                        .short('p')
+                        .long("package")
+                        .value_name("NAME")
+                        .multiple_occurrences(true)
+                        .conflicts_with_all(&["stdin", "recursive"])
+                        .help("Format only the workspace member NAME; may be repeated"),
+                )
+                .arg(
+                    Arg::new("preserve-comments")
+                        .long("preserve-comments")
+                        .value_name("MODE")
+                        .possible_values(["none", "attached", "all"])
+                        .default_value("attached")
+                        .help(
+                            "Which comments to keep: none, only those attached to an entry, or all",
+                        ),
+                )
+                .arg(
+                    Arg::new("stdin")
+                        .long("stdin")
+                        .conflicts_with_all(&["check", "recursive", "assert-idempotent"])
+                        .help("Format a manifest read from stdin and write it to stdout"),
+                )
+                .arg(
+                    Arg::new("manifest-path")
+                        .long("manifest-path")
+                        .value_name("PATH")
+                        .allow_invalid_utf8(true)
+                        .requires("stdin")
+                        .help(
+                            "Where the manifest read from stdin lives, for resolving relative \
+                             paths [default: ./Cargo.toml]",
+                        ),
+                )
+                .arg(
+                    Arg::new("max-width")
+                        .long("max-width")
+                        .value_name("N")
+                        .validator(|v| v.parse::<usize>())
+                        .help(
+                            "Split arrays that don't fit in N columns onto one line per element \
+                             [default: 100]",
+                        ),
+                )
+                .arg(
+                    Arg::new("preserve-dependency-groups")
+                        .long("preserve-dependency-groups")
+                        .help("Keep blank lines that separate groups of dependencies"),
+                )
+                .arg(
+                    Arg::new("preserve-profile-order")
+                        .long("preserve-profile-order")
+                        .help("Keep profile package overrides in the order they were written"),
+                )
+                .arg(
+                    Arg::new("preserve-inferred")
+                        .long("preserve-inferred")
+                        .help("Write target paths, build, readme, and resolver even when cargo infers them"),
+                )
+                .arg(
+                    Arg::new("recursive")
+                        .long("recursive")
+                        .value_name("DIR")
+                        .allow_invalid_utf8(true)
+                        .help("Format every Cargo.toml found beneath DIR"),
+                )
+                .arg(
+                    Arg::new("respect-gitignore")
+                        .long("respect-gitignore")
+                        .requires("recursive")
+                        .help("Skip manifests ignored by git when walking a directory"),
+                )
+                .arg(
+                    Arg::new("print-config")
+                        .long("print-config")
+                        .conflicts_with_all(&["check", "diff", "stdin", "recursive", "assert-idempotent"])
+                        .help("Print the options in effect as a .manifmt.toml file and exit"),
+                )
+                .arg(
+                    Arg::new("assert-idempotent")
+                        .long("assert-idempotent")
+                        .help("Format each manifest twice and fail if the passes disagree"),
+                )
+                .arg(
+                    Arg::new("multiline-strings")
+                        .long("multiline-strings")
+                        .help("Wrap descriptions that don't fit within the max width"),
+                )
+                .arg(
+                    Arg::new("normalize-authors")
+                        .long("normalize-authors")
+                        .help("Normalize whitespace in authors and warn about malformed entries"),
+                )
+                .arg(
+                    Arg::new("normalize-license")
+                        .long("normalize-license")
+                        .help("Rewrite license as a canonical SPDX expression"),
+                )
+                .arg(
+                    Arg::new("warn-publish")
+                        .long("warn-publish")
+                        .help("Warn about publishable packages missing fields crates.io requires"),
+                ),
+        )
+}
 
-    for (platform, mut deps) in deps {
-        if !deps.is_empty() {
-            if let Some(platform) = platform {
-                writeln!(w, "\n[target.{}.dependencies]", TomlStr(platform))?;
-            } else {
-                writeln!(w, "\n[dependencies]")?;
-            }
-            deps.sort_by_key(|dep| dep.name_in_toml());
-            for dep in deps {
-                render_dependency(w, base, dep, extra)?;
-            }
+fn run() -> Result<Status, Box<dyn Error>> {
+    let matches = cli().get_matches();
+    let matches = matches.subcommand_matches("manifmt").unwrap();
+    let cli_opts = Options {
+        only: match matches.value_of("only") {
+            Some("dependencies") => Some(Section::Dependencies),
+            _ => None,
+        },
+        preserve_comments: match matches.value_of("preserve-comments") {
+            Some("none") => PreserveComments::None,
+            Some("all") => PreserveComments::All,
+            _ => PreserveComments::Attached,
+        },
+        dep_key_order: match matches.value_of("dep-key-order") {
+            Some("alpha") => DepKeyOrder::Alphabetical,
+            _ => DepKeyOrder::Canonical,
+        },
+        normalize_authors: matches.is_present("normalize-authors"),
+        normalize_license: matches.is_present("normalize-license"),
+        multiline_strings: matches.is_present("multiline-strings"),
+        preserve_dependency_groups: matches.is_present("preserve-dependency-groups"),
+        preserve_profile_order: matches.is_present("preserve-profile-order"),
+        warn_publish: matches.is_present("warn-publish"),
+        ..Options::default()
+    };
+    // Settings from the config file next to the workspace root apply unless
+    // they are overridden on the command line.
+    let options_for = |root: &Path| -> io::Result<Options> {
+        let mut opts = cli_opts.clone();
+        opts.read_config_file(root)?;
+        if matches.occurrences_of("max-width") > 0 {
+            opts.max_width = matches.value_of_t_or_exit("max-width");
         }
-    }
-
-    if !dev_deps.is_empty() {
-        writeln!(w, "\n[dev-dependencies]")?;
-        dev_deps.sort_by_key(|dep| dep.name_in_toml());
-        for dep in dev_deps {
-            render_dependency(w, base, dep, extra)?;
+        if matches.is_present("preserve-inferred") {
+            opts.preserve_inferred = true;
         }
-    }
-
-    if !build_deps.is_empty() {
-        writeln!(w, "\n[build-dependencies]")?;
-        build_deps.sort_by_key(|dep| dep.name_in_toml());
-        for dep in build_deps {
-            render_dependency(w, base, dep, extra)?;
+        Ok(opts)
+    };
+    let check = matches.is_present("check");
+    let diff = matches.is_present("diff");
+    // Like cargo, color is only used on a terminal, and never when NO_COLOR
+    // is set to a non-empty value.
+    let color = !matches.is_present("no-color")
+        && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && io::stdout().is_terminal();
+    let packages: Vec<&str> = matches.values_of("package").into_iter().flatten().collect();
+    let config = Config::default()?;
+    if matches.is_present("print-config") {
+        let cwd = env::current_dir()?;
+        let manifest = important_paths::find_root_manifest_for_wd(&cwd)?;
+        let root = match find_workspace_root(&manifest, &config)? {
+            Some(root) => root,
+            None => manifest,
+        };
+        let opts = options_for(manifest_dir(&root)?)?;
+        print!("{}", opts.config_file());
+        return Ok(Status::Clean);
+    }
+    if matches.is_present("stdin") {
+        let cwd = env::current_dir()?;
+        let path = match matches.value_of_os("manifest-path") {
+            Some(path) => paths::normalize_path(&cwd.join(path)),
+            None => cwd.join("Cargo.toml"),
+        };
+        let root = match find_workspace_root(&path, &config)? {
+            Some(root) => root,
+            None => path.clone(),
+        };
+        let opts = options_for(manifest_dir(&root)?)?;
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        let (out, warnings) = render_source(source, &path, &config, &opts)?;
+        print_warnings(&warnings);
+        io::stdout().write_all(&out)?;
+        return Ok(Status::Clean);
+    }
+    let roots = match matches.value_of_os("recursive") {
+        Some(dir) => find_manifests(Path::new(dir), matches.is_present("respect-gitignore"))?,
+        None => {
+            let cwd = env::current_dir()?;
+            vec![important_paths::find_root_manifest_for_wd(&cwd)?]
         }
-    }
-
-    let mut features = HashMap::new();
-    for (name, specs) in manifest.summary().features() {
-        let value: Vec<_> = specs
-            .iter()
-            .map(|s| {
-                let s = s.to_string();
-                match s.strip_prefix("dep:") {
-                    None => s,
-                    Some(s) => s.to_owned(),
+    };
+    let mut status = Status::Clean;
+    let mut failed = 0;
+    for (root, paths) in workspace_manifests(&roots, &config, &packages)? {
+        let opts = options_for(&root)?;
+        let results = format_members(&paths, &opts, matches.is_present("assert-idempotent"));
+        for (path, result) in paths.into_iter().zip(results) {
+            let (current, out, warnings) = match result {
+                Ok(result) => result,
+                Err(chain) => {
+                    let context = format!("failed to format `{}`", path.display());
+                    print_error(&[vec![context], chain].concat());
+                    failed += 1;
+                    continue;
                 }
-            })
-            .collect();
-        if value.len() != 1 || value[0] != name.as_str() {
-            features.insert(name, value);
-        }
-    }
-    if !features.is_empty() {
-        writeln!(w, "\n[features]")?;
-        for (name, value) in features {
-            if let Some(comment) = extra.comments.get(&format!("features.{}", name)) {
-                write!(w, "{}", comment)?;
-            }
-            writeln!(w, "{} = {}", name, TomlFlatArray(&value))?;
-        }
-    }
-
-    Ok(())
-}
-
-fn render_metadata<W>(w: &mut W, key_prefix: &str, metadata: &toml::value::Table) -> io::Result<()>
-where
-    W: io::Write,
-{
-    let mut non_table_buf = Vec::new();
-    let mut table_buf = Vec::new();
-
-    for (key, value) in metadata {
-        match value {
-            toml::Value::Table(table) => {
-                let new_prefix = format!("{}.{}", key_prefix, key);
-                render_metadata(&mut table_buf, &new_prefix, table)?;
+            };
+            print_warnings(&warnings);
+            if check || diff {
+                if current != out {
+                    let name = path.display().to_string();
+                    let current = String::from_utf8_lossy(&current);
+                    let out = String::from_utf8_lossy(&out);
+                    if check {
+                        // The paths of unformatted manifests go to stdout, so
+                        // they can be piped elsewhere, and the diffs to stderr.
+                        let rel = pathdiff::diff_paths(&path, &env::current_dir()?);
+                        println!("{}", rel.as_deref().unwrap_or(&path).display());
+                        write_diff(&mut io::stderr(), &name, &current, &out, false)?;
+                        status = Status::Unformatted;
+                    } else {
+                        write_diff(&mut io::stdout(), &name, &current, &out, color)?;
+                    }
+                }
+                continue;
             }
-            toml::Value::Array(array) => {
-                let mut s = format!("{} = {}", key, TomlFlatArray(array));
-                if s.len() > 100 {
-                    s = format!("{} = {}", key, TomlPrettyArray(array));
+            if current != out {
+                if let Err(err) = fs::write(&path, &out) {
+                    let context = format!("failed to write `{}`", path.display());
+                    print_error(&[vec![context], error_chain(&err)].concat());
+                    failed += 1;
+                    continue;
                 }
-                writeln!(non_table_buf, "{}", s)?;
             }
-            _ => writeln!(non_table_buf, "{} = {}", key, value)?,
         }
     }
-
-    if !non_table_buf.is_empty() {
-        writeln!(w, "\n[{}]", key_prefix)?;
-        w.write(&non_table_buf)?;
+    if failed > 0 {
+        return Err(format!("{} manifest(s) could not be formatted", failed).into());
     }
-
-    w.write(&table_buf)?;
-    Ok(())
+    Ok(status)
 }
 
-fn render_target<W>(w: &mut W, base: &Path, package_name: &str, target: &Target) -> io::Result<()>
-where
-    W: io::Write,
-{
-    let mut buf = Vec::new();
-    let path = rel_path(base, target.src_path().path().unwrap());
-    let at_std_path = match target.kind() {
-        TargetKind::Lib(_) => path == "src/lib.rs",
-        TargetKind::Bin => {
-            path == "src/main.rs"
-                || path == format!("src/bin/{}/main.rs", target.name())
-                || path == format!("src/bin/{}.rs", target.name())
-        }
-        TargetKind::Test => {
-            path == format!("tests/{}/main.rs", target.name())
-                || path == format!("tests/{}.rs", target.name())
-        }
-        TargetKind::Bench => {
-            path == format!("benches/{}/main.rs", target.name())
-                || path == format!("benches/{}.rs", target.name())
-        }
-        TargetKind::ExampleLib(_) | TargetKind::ExampleBin => {
-            path == format!("examples/{}/main.rs", target.name())
-                || path == format!("examples/{}.rs", target.name())
-        }
-        _ => false,
-    };
-    if let TargetKind::Lib(crate_types) = target.kind() {
-        for crate_type in crate_types {
-            match crate_type {
-                CrateType::ProcMacro => writeln!(buf, "proc-macro = true")?,
-                _ => (),
-            }
+// A workspace's root directory and the manifests of the members to format.
+type Members = (PathBuf, Vec<PathBuf>);
+
+// Loads the workspace of each manifest in `roots` and returns its members,
+// limited to `packages` if any are named. Each manifest is returned once,
+// however many of `roots` lead to the same workspace.
+fn workspace_manifests(
+    roots: &[PathBuf],
+    config: &Config,
+    packages: &[&str],
+) -> Result<Vec<Members>, Box<dyn Error>> {
+    let mut workspaces = vec![];
+    let mut seen = HashSet::new();
+    for root in roots {
+        if seen.contains(root) {
+            continue;
         }
-    }
-    if !at_std_path {
-        writeln!(buf, "path = {}", TomlStr(path))?;
-    }
-    if !target.harness() {
-        writeln!(buf, "harness = false")?;
-    }
-    if !target.documented() && target.is_lib() {
-        writeln!(buf, "doc = false")?;
-    }
-    if !buf.is_empty() {
-        writeln!(
-            w,
-            "\n[{}]",
-            match target.kind() {
-                TargetKind::Lib(_) => "lib",
-                TargetKind::Bin => "[bin]",
-                TargetKind::Test => "[test]",
-                TargetKind::Bench => "[bench]",
-                TargetKind::ExampleLib(_) | TargetKind::ExampleBin => "[example]",
-                TargetKind::CustomBuild => unreachable!(),
+        let workspace = Workspace::new(root, config).map_err(explain_edition_error)?;
+        for name in packages {
+            if !workspace.members().any(|package| package.name() == *name) {
+                return Err(format!(
+                    "package `{}` is not a member of the workspace at `{}`",
+                    name,
+                    root.display()
+                )
+                .into());
             }
-        )?;
-        if !(target.is_lib() && target.name() == package_name) {
-            writeln!(w, "name = {}", TomlStr(target.name()))?;
-        }
-        w.write(&buf)?;
-    }
-    Ok(())
-}
-
-fn render_dependency<W>(
-    w: &mut W,
-    base: &Path,
-    dep: &Dependency,
-    extra: &ManifestExtra,
-) -> io::Result<()>
-where
-    W: io::Write,
-{
-    let toml_key = match dep.platform() {
-        None => format!("dependencies.{}", dep.name_in_toml()),
-        Some(platform) => format!(
-            "target.{}.dependencies.{}",
-            TomlStr(platform),
-            dep.name_in_toml()
-        ),
-    };
-    if let Some(comment) = extra.comments.get(&toml_key) {
-        write!(w, "{}", comment)?;
-    }
-    write!(w, "{} = ", dep.name_in_toml())?;
-    let mut meta: Vec<(&'static str, Box<dyn fmt::Display>)> = vec![];
-    if dep.package_name() != dep.name_in_toml() {
-        meta.push(("package", Box::new(TomlStr(dep.package_name()))));
-    }
-    let source_id = dep.source_id();
-    if source_id.is_path() {
-        let url = source_id.url();
-        meta.push(("path", Box::new(TomlStr(rel_path(base, url.path())))));
-    } else if let Some(git_ref) = source_id.git_reference() {
-        meta.push(("git", Box::new(TomlStr(source_id.url().clone()))));
-        match git_ref {
-            GitReference::Tag(tag) => meta.push(("tag", Box::new(TomlStr(tag)))),
-            GitReference::Branch(branch) if branch != "master" => {
-                meta.push(("branch", Box::new(TomlStr(branch))))
-            }
-            GitReference::Rev(rev) => meta.push(("rev", Box::new(TomlStr(rev)))),
-            _ => (),
-        }
-    }
-    if !dep.uses_default_features() {
-        meta.push(("default-features", Box::new("false")));
-    }
-    if !dep.features().is_empty() {
-        meta.push(("features", Box::new(TomlFlatArray(dep.features()))));
-    }
-    if dep.is_optional() {
-        meta.push(("optional", Box::new("true")));
-    }
-    if meta.is_empty() {
-        write!(w, "{}\n", TomlVersion(dep.version_req()))?;
-    } else {
-        if dep.version_req().to_string() != "*" {
-            meta.insert(0, ("version", Box::new(TomlVersion(dep.version_req()))));
         }
-        write!(
-            w,
-            "{{ {} }}\n",
-            meta.iter()
-                .map(|(k, v)| format!("{} = {}", k, v))
-                .collect::<Vec<_>>()
-                .join(", ")
-        )?;
+        let members = workspace
+            .members()
+            .filter(|package| packages.is_empty() || packages.contains(&&*package.name()));
+        let paths: Vec<_> = members
+            .map(|package| package.manifest_path().to_owned())
+            .filter(|path| seen.insert(path.clone()))
+            .collect();
+        workspaces.push((workspace.root().to_owned(), paths));
+        seen.insert(root.clone());
     }
-    Ok(())
-}
-
-fn rel_path(base: &Path, path: impl AsRef<Path>) -> String {
-    pathdiff::diff_paths(path.as_ref(), base)
-        .unwrap()
-        .to_string_lossy()
-        .into_owned()
+    Ok(workspaces)
 }
 
-struct ManifestExtra {
-    autobenches: bool,
-    autobins: bool,
-    autoexamples: bool,
-    autotests: bool,
-    comments: HashMap<String, String>,
+// The source of a manifest, its formatted form, and any warnings about it.
+type Output = (Vec<u8>, Vec<u8>, Vec<String>);
+
+// The output for a manifest, or the chain of errors that prevented formatting
+// it.
+type Formatted = Result<Output, Vec<String>>;
+
+// Formats the manifests at `paths` on a pool of threads, returning the
+// results in the same order as `paths`. Nothing is written, as rendering a
+// member may read the workspace root's manifest.
+fn format_members(paths: &[PathBuf], opts: &Options, assert_idempotent: bool) -> Vec<Formatted> {
+    let next = AtomicUsize::new(0);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut results: Vec<_> = thread::scope(|s| {
+        let workers: Vec<_> = (0..threads.min(paths.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    // cargo's `Config` and the packages it loads can't be
+                    // shared between threads, so each worker loads the
+                    // manifests it formats itself.
+                    let config = Config::default();
+                    let mut results = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(i) else { break };
+                        let result = match &config {
+                            Ok(config) => format_member(path, config, opts, assert_idempotent)
+                                .map_err(|err| error_chain(&*err)),
+                            Err(err) => Err(error_chain(err.as_ref())),
+                        };
+                        results.push((i, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
 }
 
-struct TomlStr<S>(S);
-
-impl<S> fmt::Display for TomlStr<S>
-where
-    S: fmt::Display,
-{
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.0.to_string().fmt_toml(f)
+// Formats the manifest at `path`. With `assert_idempotent`, the output is
+// formatted again as though it had been written back, and the two passes must
+// agree.
+fn format_member(
+    path: &Path,
+    config: &Config,
+    opts: &Options,
+    assert_idempotent: bool,
+) -> Result<Output, Box<dyn Error>> {
+    let current = fs::read_to_string(path)?;
+    let (out, warnings) = render_source(current.clone(), path, config, opts)?;
+    if assert_idempotent {
+        let first = String::from_utf8(out.clone())?;
+        let (second, _) = render_source(first.clone(), path, config, opts)?;
+        let second = String::from_utf8(second)?;
+        if first != second {
+            let diff = TextDiff::from_lines(&first, &second)
+                .unified_diff()
+                .header("first pass", "second pass")
+                .to_string();
+            return Err(format!(
+                "formatting is not idempotent; please report this as a bug, \
+                 including this diff of the two passes:\n{}",
+                diff
+            )
+            .into());
+        }
     }
+    Ok((current.into_bytes(), out, warnings))
 }
 
-struct TomlFlatArray<'a, S>(&'a [S]);
-
-impl<'a, S> fmt::Display for TomlFlatArray<'a, S>
-where
-    S: TomlDisplay,
-{
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_char('[')?;
-        for (i, s) in self.0.iter().enumerate() {
-            if i > 0 {
-                f.write_str(", ")?;
-            }
-            s.fmt_toml(f)?;
-        }
-        f.write_char(']')
+// cargo's own error for an edition it doesn't know suggests upgrading cargo,
+// but it is the cargo built into cargo-manifmt that is too old.
+fn explain_edition_error(err: anyhow::Error) -> anyhow::Error {
+    let edition_regex = regex!(r"older than the `([^`]+)` edition");
+    let edition = err.chain().find_map(|cause| {
+        let cause = cause.to_string();
+        let caps = edition_regex.captures(&cause)?;
+        Some(caps[1].to_owned())
+    });
+    match edition {
+        Some(edition) => err.context(format!(
+            "edition `{}` is not supported by the version of cargo that cargo-manifmt was \
+             built against, which knows editions up to {}",
+            edition,
+            Edition::LATEST_STABLE
+        )),
+        None => err,
     }
 }
 
-struct TomlPrettyArray<'a, S>(&'a [S]);
-
-impl<'a, S> fmt::Display for TomlPrettyArray<'a, S>
+// Writes a unified diff from `old` to `new`, the formatted form of the
+// manifest at `path`.
+fn write_diff<W>(w: &mut W, path: &str, old: &str, new: &str, color: bool) -> io::Result<()>
 where
-    S: TomlDisplay,
+    W: Write,
 {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_char('[')?;
-        if self.0.len() > 1 {
-            f.write_char('\n')?;
-        }
-        for s in self.0 {
-            if self.0.len() > 1 {
-                f.write_str("    ")?;
-            }
-            s.fmt_toml(f)?;
-            if self.0.len() > 1 {
-                f.write_str(",\n")?;
-            }
-        }
-        f.write_char(']')
-    }
-}
-
-struct TomlVersion<'a>(&'a OptVersionReq);
-
-impl<'a> fmt::Display for TomlVersion<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let s = self.0.to_string();
-        let version_regex =
-            regex!(r#"\^(?P<major>[0-9]+)(\.(?P<minor>[0-9]+)(\.(?P<patch>[0-9]+))?)?"#);
-        if let Some(caps) = version_regex.captures(&s) {
-            write!(
-                f,
-                "\"{}.{}.{}\"",
-                caps.name("major").map_or("0", |m| m.as_str()),
-                caps.name("minor").map_or("0", |m| m.as_str()),
-                caps.name("patch").map_or("0", |m| m.as_str())
-            )
+    let diff = TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(path, path)
+        .to_string();
+    if !color {
+        return w.write_all(diff.as_bytes());
+    }
+    for line in diff.split_inclusive('\n') {
+        let style = if line.starts_with("---") || line.starts_with("+++") {
+            "1"
+        } else if line.starts_with("@@") {
+            "36"
+        } else if line.starts_with('-') {
+            "31"
+        } else if line.starts_with('+') {
+            "32"
         } else {
-            write!(f, "{}", TomlStr(s))
-        }
+            w.write_all(line.as_bytes())?;
+            continue;
+        };
+        let text = line.trim_end_matches('\n');
+        write!(w, "\x1b[{}m{}\x1b[0m{}", style, text, &line[text.len()..])?;
     }
+    Ok(())
 }
 
-trait TomlDisplay {
-    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result;
-}
-
-impl TomlDisplay for toml::Value {
-    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Display::fmt(self, f)
+fn find_manifests(dir: &Path, respect_gitignore: bool) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut manifests = vec![];
+    let walker = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .git_ignore(respect_gitignore)
+        .git_exclude(respect_gitignore)
+        .git_global(respect_gitignore)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_name() == "Cargo.toml" && entry.path().is_file() {
+            manifests.push(paths::normalize_path(
+                &env::current_dir()?.join(entry.path()),
+            ));
+        }
     }
+    Ok(manifests)
 }
 
-impl TomlDisplay for &str {
-    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.contains('"') && !self.contains('\'') {
-            f.write_char('\'')?;
-            f.write_str(self)?;
-            return f.write_char('\'');
+// Renders manifest source as though it were stored at `path`, which need not
+// exist on disk, returning the output and any warnings.
+fn render_source(
+    source: String,
+    path: &Path,
+    config: &Config,
+    opts: &Options,
+) -> Result<(Vec<u8>, Vec<String>), Box<dyn Error>> {
+    let base = manifest_dir(path)?;
+    let (manifest, _) = read_manifest_from_str(&source, path, SourceId::for_path(base)?, config)
+        .map_err(explain_edition_error)?;
+    match manifest {
+        EitherManifest::Real(manifest) => {
+            let extra = parse_manifest_from_str(source, path)?;
+            let mut out: Vec<u8> = vec![];
+            let warnings = render_manifest(&mut out, base, &manifest, &extra, opts)?;
+            Ok((out, warnings))
         }
-
-        f.write_char('\"')?;
-        for ch in self.chars() {
-            match ch {
-                '\u{8}' => f.write_str("\\b")?,
-                '\u{9}' => f.write_str("\\t")?,
-                '\u{a}' => f.write_str("\\n")?,
-                '\u{c}' => f.write_str("\\f")?,
-                '\u{d}' => f.write_str("\\r")?,
-                '\u{22}' => f.write_str("\\\"")?,
-                '\u{5c}' => f.write_str("\\\\")?,
-                c if c < '\u{1f}' => write!(f, "\\u{:04X}", ch as u32)?,
-                ch => f.write_char(ch)?,
-            }
-        }
-        f.write_char('\"')
+        // Virtual manifests are left untouched, just like when formatting a
+        // workspace in place.
+        EitherManifest::Virtual(_) => Ok((source.into_bytes(), vec![])),
     }
 }
 
-impl TomlDisplay for String {
-    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.as_str().fmt_toml(f)
-    }
+fn manifest_dir(path: &Path) -> Result<&Path, Box<dyn Error>> {
+    path.parent()
+        .ok_or_else(|| format!("`{}` is not a path to a manifest", path.display()).into())
 }
 
-impl TomlDisplay for InternedString {
-    fn fmt_toml(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.as_str().fmt_toml(f)
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // A workspace with a root package and a member, next to a vendored crate
+    // that git ignores.
+    fn fixture() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let files = &[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"root\"\nversion = \"0.1.0\"\n\n[workspace]\nmembers = [\"member\"]\n",
+            ),
+            ("src/lib.rs", ""),
+            (
+                "member/Cargo.toml",
+                "[package]\nname = \"member\"\nversion = \"0.1.0\"\n",
+            ),
+            ("member/src/lib.rs", ""),
+            (
+                "vendor/dep/Cargo.toml",
+                "[package]\nname = \"dep\"\nversion = \"0.1.0\"\n\n[workspace]\n",
+            ),
+            ("vendor/dep/src/lib.rs", ""),
+            (".gitignore", "/vendor\n"),
+            (".git/HEAD", "ref: refs/heads/main\n"),
+        ];
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn gitignored_manifests() {
+        let dir = fixture();
+        let root = dir.path();
+        let all = vec![
+            root.join("Cargo.toml"),
+            root.join("member/Cargo.toml"),
+            root.join("vendor/dep/Cargo.toml"),
+        ];
+        assert_eq!(find_manifests(root, false).unwrap(), all);
+        assert_eq!(find_manifests(root, true).unwrap(), &all[..2]);
+    }
+
+    #[test]
+    fn members_formatted_once() {
+        let dir = fixture();
+        let root = dir.path();
+        let config = Config::default().unwrap();
+        // The walk finds the member both on its own and through the root.
+        let roots = find_manifests(root, true).unwrap();
+        let workspaces = workspace_manifests(&roots, &config, &[]).unwrap();
+        let mut paths: Vec<_> = workspaces
+            .into_iter()
+            .flat_map(|(_, paths)| paths)
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![root.join("Cargo.toml"), root.join("member/Cargo.toml")]
+        );
     }
 }
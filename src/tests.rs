@@ -0,0 +1,1681 @@
+// Copyright 2019 Nikhil Benesch.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+use std::path::Path;
+
+use cargo::core::{EitherManifest, Manifest, Shell, SourceId};
+use cargo::util::config::{self, Config};
+use cargo::util::toml::read_manifest_from_str;
+use tempfile::TempDir;
+
+use super::*;
+
+// Creates a crate in a temporary directory from `files`, given as pairs of
+// paths and contents.
+fn fixture(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, contents) in files {
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+// Loads the manifest at `path` and returns it along with its formatted form
+// and any warnings.
+fn load_and_format(path: &Path, opts: &Options) -> (Manifest, String, Vec<String>) {
+    let source = fs::read_to_string(path).unwrap();
+    let base = path.parent().unwrap();
+    // Run as though from the package root, so that the fixture can carry its
+    // own cargo configuration.
+    let config = Config::new(
+        Shell::new(),
+        base.to_owned(),
+        config::homedir(base).unwrap(),
+    );
+    let source_id = SourceId::for_path(base).unwrap();
+    let manifest = match read_manifest_from_str(&source, path, source_id, &config) {
+        Ok((EitherManifest::Real(manifest), _)) => manifest,
+        Ok((EitherManifest::Virtual(_), _)) => panic!("`{}` is virtual", path.display()),
+        Err(err) => panic!("cargo rejected `{}`: {:?}\n{}", path.display(), err, source),
+    };
+    let extra = parse_manifest_from_str(source, path).unwrap();
+    let (out, warnings) = format_manifest(base, &manifest, &extra, opts).unwrap();
+    (manifest, out, warnings)
+}
+
+// Formats the manifest at `path` and writes the result back, then formats it
+// again. Asserts that the second pass changes nothing and that cargo sees
+// the same package before and after formatting. Returns the formatted
+// manifest.
+fn format_twice(path: &Path, opts: &Options) -> String {
+    let (before, first, _) = load_and_format(path, opts);
+    fs::write(path, &first).unwrap();
+    let (after, second, _) = load_and_format(path, opts);
+    assert_eq!(first, second, "formatting is not idempotent");
+
+    // A lib target named with underscores instead of the package's hyphens
+    // is the same crate, and the order of crate types doesn't matter.
+    let targets = |manifest: &Manifest| {
+        let mut targets = manifest.targets().to_vec();
+        for target in &mut targets {
+            let name = target.crate_name();
+            target.set_name(&name);
+            let kind = match target.kind().clone() {
+                TargetKind::Lib(mut crate_types) => {
+                    crate_types.sort();
+                    TargetKind::Lib(crate_types)
+                }
+                TargetKind::ExampleLib(mut crate_types) => {
+                    crate_types.sort();
+                    TargetKind::ExampleLib(crate_types)
+                }
+                kind => kind,
+            };
+            target.set_kind(kind);
+        }
+        targets.sort();
+        targets
+    };
+    assert!(
+        targets(&before) == targets(&after),
+        "targets changed:\n{}",
+        first
+    );
+    let deps = (before.dependencies(), after.dependencies());
+    assert!(
+        deps.0.len() == deps.1.len() && deps.0.iter().all(|dep| deps.1.contains(dep)),
+        "dependencies changed:\n{}",
+        first
+    );
+    assert_eq!(before.summary().features(), after.summary().features());
+    assert_eq!(before.metadata(), after.metadata());
+    assert_eq!(before.custom_metadata(), after.custom_metadata());
+    let resolver = |manifest: &Manifest| {
+        manifest.resolve_behavior().unwrap_or_else(|| {
+            match manifest.edition() >= Edition::Edition2021 {
+                true => ResolveBehavior::V2,
+                false => ResolveBehavior::V1,
+            }
+        })
+    };
+    assert_eq!(resolver(&before), resolver(&after));
+    let profiles = |manifest: &Manifest| {
+        let profiles = manifest.profiles()?;
+        Some(toml::Value::try_from(profiles).unwrap())
+    };
+    assert_eq!(profiles(&before), profiles(&after));
+    assert_eq!(before.patch(), after.patch());
+    assert_eq!(before.replace(), after.replace());
+    first
+}
+
+#[test]
+fn idempotent() {
+    let manifests = &[
+        r#"
+[package]
+name = "wildcard"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+serde = "*"
+foo = { git = "https://github.com/example/foo", version = "*" }
+bar = { git = "https://github.com/example/bar", branch = "main" }
+baz = { version = "^1", path = "baz" }
+"#,
+        r#"
+[package]
+name = "everything"
+version = "1.2.3"
+authors = ["Jane Doe <jane@example.com>", "John Doe <john@example.com>"]
+description = "A package that uses most of what cargo-manifmt knows about"
+edition = "2021"
+keywords = ["one", "two", "three", "four", "five"]
+categories = ["development-tools::cargo-plugins", "command-line-utilities"]
+license = "MIT OR Apache-2.0"
+readme = "README.md"
+build = "build.rs"
+
+[package.metadata.docs.rs]
+all-features = true
+
+[lib]
+crate-type = ["cdylib", "rlib"]
+
+[[bin]]
+name = "tool"
+required-features = ["cli"]
+
+[features]
+# The command-line interface.
+cli = ["dep:clap"]
+default = []
+
+[dependencies]
+# Used by the CLI.
+clap = { version = "3.2", optional = true, features = ["derive"] }
+log = "0.4" # For diagnostics.
+
+[dev-dependencies]
+tempfile = "3"
+
+[target.'cfg(unix)'.dependencies]
+libc = { version = "0.2", default-features = false }
+
+[build-dependencies]
+cc = "1.0"
+
+[profile.release]
+lto = true
+codegen-units = 1
+"#,
+    ];
+    let modes = &[
+        PreserveComments::None,
+        PreserveComments::Attached,
+        PreserveComments::All,
+    ];
+    for manifest in manifests {
+        for &preserve_comments in modes {
+            let dir = fixture(&[
+                ("Cargo.toml", manifest),
+                ("src/lib.rs", ""),
+                ("src/bin/tool.rs", ""),
+                ("build.rs", ""),
+                (
+                    "baz/Cargo.toml",
+                    "[package]\nname = \"baz\"\nversion = \"1.0.0\"\n",
+                ),
+                ("baz/src/lib.rs", ""),
+            ]);
+            let opts = Options {
+                preserve_comments,
+                ..Options::default()
+            };
+            format_twice(&dir.path().join("Cargo.toml"), &opts);
+        }
+    }
+}
+
+#[test]
+fn lib_name() {
+    let cases = &[
+        ("", false),
+        ("[lib]\nname = \"my_crate\"\n", false),
+        ("[lib]\nname = \"custom\"\n", true),
+    ];
+    for (lib, kept) in cases {
+        let manifest = format!(
+            "[package]\nname = \"my-crate\"\nversion = \"0.1.0\"\n\n{}",
+            lib
+        );
+        let dir = fixture(&[("Cargo.toml", &manifest), ("src/lib.rs", "")]);
+        let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+        assert_eq!(out.contains("[lib]\nname = \"custom\"\n"), *kept, "{}", out);
+        assert!(*kept || !out.contains("[lib]"), "{}", out);
+    }
+}
+
+#[test]
+fn directive_and_cargo_features() {
+    let manifest = r#"# @generated by a tool
+cargo-features = ["edition2021"]
+
+# The package.
+[package]
+name = "generated"
+version = "0.1.0"
+edition = "2021"
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}
+
+#[test]
+fn preserve_comments() {
+    let manifest = r#"[package]
+name = "comments"
+version = "0.1.0"
+
+# floating note
+
+# attached note
+[dependencies]
+# floating dependency note
+
+# attached dependency note
+log = "0.4" # trailing note
+"#;
+    let expected = &[
+        (
+            PreserveComments::None,
+            r#"[package]
+name = "comments"
+version = "0.1.0"
+edition = "2015"
+
+[dependencies]
+log = "0.4"
+"#,
+        ),
+        (
+            PreserveComments::Attached,
+            r#"[package]
+name = "comments"
+version = "0.1.0"
+edition = "2015"
+
+# attached note
+[dependencies]
+# attached dependency note
+log = "0.4"
+"#,
+        ),
+        (
+            PreserveComments::All,
+            r#"[package]
+name = "comments"
+version = "0.1.0"
+edition = "2015"
+
+# floating note
+
+# attached note
+[dependencies]
+# floating dependency note
+
+# attached dependency note
+log = "0.4" # trailing note
+"#,
+        ),
+    ];
+    for (preserve_comments, expected) in expected {
+        let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+        let opts = Options {
+            preserve_comments: *preserve_comments,
+            ..Options::default()
+        };
+        let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+        assert_eq!(out, *expected, "{:?}", preserve_comments);
+    }
+}
+
+#[test]
+fn multiline_strings() {
+    let description = "A crate with a description long enough that it has to be wrapped. \
+        It mentions #rustlang and [brackets] in the places where lines break,\n\
+        has a line break of its own, \"\"\"quotes\"\"\", and a \\ backslash, and ends \
+        with a quote\"";
+    assert!(description.len() >= 200);
+    for crlf in &[false, true] {
+        let manifest = format!(
+            "[package]\nname = \"wrapped\"\nversion = \"0.1.0\"\ndescription = {}\n",
+            TomlStr(description)
+        );
+        let manifest = match crlf {
+            true => manifest.replace('\n', "\r\n"),
+            false => manifest,
+        };
+        let dir = fixture(&[("Cargo.toml", &manifest), ("src/lib.rs", "")]);
+        for max_width in &[40, 70, 100] {
+            let opts = Options {
+                multiline_strings: true,
+                max_width: *max_width,
+                ..Options::default()
+            };
+            let path = dir.path().join("Cargo.toml");
+            let out = format_twice(&path, &opts);
+            assert!(out.contains("description = \"\"\""), "{}", out);
+            let toml: toml::Value = toml::from_str(&out).unwrap();
+            assert_eq!(toml["package"]["description"].as_str(), Some(description));
+        }
+    }
+}
+
+#[test]
+fn preserve_inferred() {
+    let manifests = &[
+        r#"[package]
+name = "inferred"
+version = "0.1.0"
+readme = "README.md"
+edition = "2021"
+resolver = "2"
+build = "build.rs"
+
+[lib]
+path = "src/lib.rs"
+
+[[bin]]
+name = "inferred"
+path = "src/main.rs"
+"#,
+        r#"[package]
+name = "inferred"
+version = "0.1.0"
+edition = "2021"
+
+[workspace]
+resolver = "2"
+"#,
+    ];
+    for manifest in manifests {
+        for preserve_inferred in &[false, true] {
+            let dir = fixture(&[
+                ("Cargo.toml", manifest),
+                ("README.md", ""),
+                ("build.rs", ""),
+                ("src/lib.rs", ""),
+                ("src/main.rs", ""),
+            ]);
+            let opts = Options {
+                preserve_inferred: *preserve_inferred,
+                ..Options::default()
+            };
+            let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+            for line in &[
+                "readme = \"README.md\"\n",
+                "resolver = \"2\"\n",
+                "build = \"build.rs\"\n",
+                "path = \"src/lib.rs\"\n",
+                "path = \"src/main.rs\"\n",
+            ] {
+                assert_eq!(out.contains(line), *preserve_inferred, "{}", out);
+            }
+        }
+    }
+}
+
+#[test]
+fn profile_overrides() {
+    let manifest = r#"[package]
+name = "profiles"
+version = "0.1.0"
+edition = "2015"
+
+[profile.release.build-override]
+opt-level = 0
+
+[profile.release.package.zlib]
+opt-level = 3
+
+[profile.release]
+lto = true
+
+[profile.release.package."*"]
+debug = false
+
+[profile.release.package.alpha]
+codegen-units = 1
+"#;
+    let sorted = r#"[package]
+name = "profiles"
+version = "0.1.0"
+edition = "2015"
+
+[profile.release]
+lto = true
+
+[profile.release.package."*"]
+debug = false
+
+[profile.release.package.alpha]
+codegen-units = 1
+
+[profile.release.package.zlib]
+opt-level = 3
+
+[profile.release.build-override]
+opt-level = 0
+"#;
+    // A profile's own keys always come before its subtables.
+    let preserved = r#"[package]
+name = "profiles"
+version = "0.1.0"
+edition = "2015"
+
+[profile.release]
+lto = true
+
+[profile.release.build-override]
+opt-level = 0
+
+[profile.release.package.zlib]
+opt-level = 3
+
+[profile.release.package."*"]
+debug = false
+
+[profile.release.package.alpha]
+codegen-units = 1
+"#;
+    for preserve_profile_order in &[false, true] {
+        let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+        let opts = Options {
+            preserve_profile_order: *preserve_profile_order,
+            ..Options::default()
+        };
+        let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+        match preserve_profile_order {
+            true => assert_eq!(out, preserved),
+            false => assert_eq!(out, sorted),
+        }
+    }
+}
+
+#[test]
+fn version_requirements() {
+    let manifest = r#"[package]
+name = "versions"
+version = "0.1.0"
+
+[dependencies]
+caret = "^1"
+compound = ">= 1.0, < 2.0"
+exact = "=1.2.3"
+minimal = "1"
+tilde = "~1.2"
+wildcard = "1.*"
+"#;
+    let expected = r#"[package]
+name = "versions"
+version = "0.1.0"
+edition = "2015"
+
+[dependencies]
+caret = "1"
+compound = ">=1.0, <2.0"
+exact = "=1.2.3"
+minimal = "1"
+tilde = "~1.2"
+wildcard = "1.*"
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn package_field_comments() {
+    let manifest = r#"[package]
+name = "annotated"
+# Bumped by the release script.
+version = "0.1.0"
+# The default, so this goes away.
+readme = "README.md"
+# Where issues are tracked.
+repository = "https://example.com/annotated"
+"#;
+    let expected = r#"[package]
+name = "annotated"
+# Bumped by the release script.
+version = "0.1.0"
+# Where issues are tracked.
+repository = "https://example.com/annotated"
+edition = "2015"
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("README.md", ""),
+        ("src/lib.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn crate_types() {
+    let manifest = r#"[package]
+name = "ffi"
+version = "0.1.0"
+edition = "2018"
+
+[lib]
+crate-type = ["staticlib", "rlib", "cdylib"]
+
+[[example]]
+name = "plugin"
+crate-type = ["dylib"]
+"#;
+    let expected = r#"[package]
+name = "ffi"
+version = "0.1.0"
+edition = "2018"
+
+[lib]
+crate-type = ["cdylib", "rlib", "staticlib"]
+
+[[example]]
+name = "plugin"
+crate-type = ["dylib"]
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/lib.rs", ""),
+        ("examples/plugin.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+
+    let manifest =
+        "[package]\nname = \"derive\"\nversion = \"0.1.0\"\n\n[lib]\nproc-macro = true\n";
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert!(out.ends_with("\n[lib]\nproc-macro = true\n"), "{}", out);
+}
+
+#[test]
+fn badges() {
+    let manifest = r#"[package]
+name = "badged"
+version = "0.1.0"
+edition = "2018"
+
+[badges]
+travis-ci = { repository = "example/badged" }
+
+[badges.maintenance]
+status = "actively-developed"
+"#;
+    let expected = r#"[package]
+name = "badged"
+version = "0.1.0"
+edition = "2018"
+
+[badges.travis-ci]
+repository = "example/badged"
+
+[badges.maintenance]
+status = "actively-developed"
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn profiles() {
+    let manifest = r#"[package]
+name = "profiled"
+version = "0.1.0"
+edition = "2018"
+
+[profile.custom]
+inherits = "release"
+opt-level = "s"
+
+[profile.release]
+codegen-units = 1
+debug = true
+lto = "fat"
+
+[profile.release.package.foo]
+opt-level = 3
+
+[profile.dev]
+opt-level = 1
+"#;
+    let expected = r#"[package]
+name = "profiled"
+version = "0.1.0"
+edition = "2018"
+
+[profile.dev]
+opt-level = 1
+
+[profile.release]
+codegen-units = 1
+debug = true
+lto = "fat"
+
+[profile.release.package.foo]
+opt-level = 3
+
+[profile.custom]
+inherits = "release"
+opt-level = "s"
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn patch_and_replace() {
+    let header = "[package]\nname = \"patched\"\nversion = \"0.1.0\"\nedition = \"2018\"\n";
+    let cases = &[
+        (
+            r#"
+[patch.crates-io]
+serde = { git = "https://github.com/example/serde", branch = "fix" }
+log = { path = "vendor/log" }
+
+[patch."https://github.com/example/other"]
+other = { git = "https://github.com/example/other-fork", rev = "abc123" }
+"#,
+            r#"
+[patch.crates-io]
+log = { path = "vendor/log" }
+serde = { git = "https://github.com/example/serde", branch = "fix" }
+
+[patch."https://github.com/example/other"]
+other = { git = "https://github.com/example/other-fork", rev = "abc123" }
+"#,
+        ),
+        (
+            r#"
+[replace]
+"libc:0.2.0" = { tag = "v0.2.0", git = "https://github.com/example/libc" }
+"#,
+            r#"
+[replace]
+"libc:0.2.0" = { git = "https://github.com/example/libc", tag = "v0.2.0" }
+"#,
+        ),
+    ];
+    for (tables, expected) in cases {
+        let manifest = format!("{}{}", header, tables);
+        let dir = fixture(&[("Cargo.toml", &manifest), ("src/lib.rs", "")]);
+        let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+        assert_eq!(out, format!("{}{}", header, expected));
+    }
+}
+
+#[test]
+fn workspace_dependencies() {
+    let root = r#"[workspace]
+members = ["member"]
+
+[workspace.dependencies]
+log = "0.4"
+serde = { version = "1.0", default-features = false }
+"#;
+    let member = r#"[package]
+name = "member"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+serde = { features = ["derive"], workspace = true, optional = true }
+log.workspace = true
+"#;
+    let expected = r#"[package]
+name = "member"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+log = { workspace = true }
+serde = { workspace = true, features = ["derive"], optional = true }
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", root),
+        ("member/Cargo.toml", member),
+        ("member/src/lib.rs", ""),
+    ]);
+    let path = dir.path().join("member/Cargo.toml");
+    let out = format_twice(&path, &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn root_workspace_dependencies() {
+    let manifest = r#"[package]
+name = "root"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+util = { workspace = true }
+
+[workspace]
+
+[workspace.dependencies]
+# Until the fix is released upstream.
+forked = { branch = "next", git = "https://github.com/example/forked" }
+util = { path = "crates/util", version = "0.1" } # Shared helpers.
+"#;
+    let expected = r#"[package]
+name = "root"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+util = { workspace = true }
+
+[workspace.dependencies]
+# Until the fix is released upstream.
+forked = { git = "https://github.com/example/forked", branch = "next" }
+util = { version = "0.1", path = "crates/util" } # Shared helpers.
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/lib.rs", ""),
+        (
+            "crates/util/Cargo.toml",
+            "[package]\nname = \"util\"\nversion = \"0.1.0\"\n",
+        ),
+        ("crates/util/src/lib.rs", ""),
+    ]);
+    // Comments at the end of a line are only kept on request.
+    let opts = Options {
+        preserve_comments: PreserveComments::All,
+        ..Options::default()
+    };
+    let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn platform_dependencies() {
+    let manifest = r#"[package]
+name = "platforms"
+version = "0.1.0"
+edition = "2018"
+
+[target.'cfg(windows)'.build-dependencies]
+winres = "0.1"
+
+[dev-dependencies]
+tempfile = "3"
+
+[target.'cfg(unix)'.dev-dependencies]
+nix = "0.26"
+
+[build-dependencies]
+cc = "1.0"
+
+[target.'cfg(windows)'.dependencies]
+winapi = "0.3"
+"#;
+    let expected = r#"[package]
+name = "platforms"
+version = "0.1.0"
+edition = "2018"
+
+[dev-dependencies]
+tempfile = "3"
+
+[build-dependencies]
+cc = "1.0"
+
+[target."cfg(unix)".dev-dependencies]
+nix = "0.26"
+
+[target."cfg(windows)".dependencies]
+winapi = "0.3"
+
+[target."cfg(windows)".build-dependencies]
+winres = "0.1"
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn max_width() {
+    let manifest = r#"[package]
+name = "wide"
+version = "0.1.0"
+authors = ["Someone With A Rather Long Name <someone.with.a.rather.long.name@example.com>"]
+keywords = ["a", "b"]
+edition = "2018"
+
+[package.metadata.release]
+pre-release-hook = ["./scripts/release.sh", "--verbose"]
+empty = []
+"#;
+    let expected = r#"[package]
+name = "wide"
+version = "0.1.0"
+authors = [
+    "Someone With A Rather Long Name <someone.with.a.rather.long.name@example.com>",
+]
+keywords = ["a", "b"]
+edition = "2018"
+
+[package.metadata.release]
+pre-release-hook = [
+    "./scripts/release.sh",
+    "--verbose",
+]
+empty = []
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let opts = Options {
+        max_width: 40,
+        ..Options::default()
+    };
+    let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn registries() {
+    let manifest = r#"[package]
+name = "private"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+by-index = { registry-index = "https://example.com/other-index", version = "2" }
+by-name = { version = "1", registry = "ours" }
+public = "1"
+"#;
+    let expected = r#"[package]
+name = "private"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+by-index = { version = "2", registry-index = "https://example.com/other-index" }
+by-name = { version = "1", registry = "ours" }
+public = "1"
+"#;
+    let config = "[registries.ours]\nindex = \"https://example.com/index\"\n";
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        (".cargo/config.toml", config),
+        ("src/lib.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn required_features() {
+    let manifest = r#"[package]
+name = "gated"
+version = "0.1.0"
+edition = "2018"
+
+[[bin]]
+name = "gated"
+required-features = ["cli"]
+
+[[example]]
+name = "demo"
+required-features = ["cli", "extra"]
+
+[features]
+cli = []
+extra = []
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/main.rs", ""),
+        ("examples/demo.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}
+
+#[test]
+fn lints() {
+    let header = "[package]\nname = \"linted\"\nversion = \"0.1.0\"\nedition = \"2021\"\n";
+    let cases = &[
+        (
+            "\n[lints]\nworkspace = true\n",
+            "\n[lints]\nworkspace = true\n",
+        ),
+        (
+            r#"
+[lints.rust]
+unsafe_code = "forbid"
+
+[lints.clippy]
+pedantic = { level = "warn", priority = -1 }
+enum_glob_use = "deny"
+"#,
+            r#"
+[lints.clippy]
+enum_glob_use = "deny"
+pedantic = { level = "warn", priority = -1 }
+
+[lints.rust]
+unsafe_code = "forbid"
+"#,
+        ),
+        (
+            "\n[workspace.lints.rust]\nunsafe_code = \"forbid\"\n",
+            "\n[workspace.lints.rust]\nunsafe_code = \"forbid\"\n",
+        ),
+    ];
+    for (tables, expected) in cases {
+        let manifest = format!("{}{}", header, tables);
+        let dir = fixture(&[("Cargo.toml", &manifest), ("src/lib.rs", "")]);
+        let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+        assert_eq!(out, format!("{}{}", header, expected));
+    }
+}
+
+#[test]
+fn git_branches() {
+    let manifest = r#"[package]
+name = "branches"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+default = { git = "https://github.com/example/default" }
+main = { git = "https://github.com/example/main", branch = "main" }
+master = { git = "https://github.com/example/master", branch = "master" }
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}
+
+#[test]
+fn line_endings() {
+    let manifest = "[package]\r\nname = \"crlf\"\r\nversion = \"0.1.0\"\r\n\r\n[dependencies]\r\nlog = \"0.4\"\r\n\r\n\r\n";
+    let expected = "[package]\r\nname = \"crlf\"\r\nversion = \"0.1.0\"\r\nedition = \"2015\"\r\n\r\n[dependencies]\r\nlog = \"0.4\"\r\n";
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+
+    let dir = fixture(&[
+        ("Cargo.toml", &expected.replace("\r\n", "\n")),
+        ("src/lib.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert!(!out.contains('\r'), "{:?}", out);
+}
+
+#[test]
+fn metadata_order_and_comments() {
+    let manifest = r#"[package]
+name = "documented"
+version = "0.1.0"
+edition = "2018"
+
+# Settings for docs.rs.
+[package.metadata.docs.rs]
+# Build with every feature enabled.
+all-features = true
+targets = ["x86_64-unknown-linux-gnu"]
+# Document private items too.
+rustdoc-args = ["--document-private-items"]
+
+[package.metadata.another-tool]
+zebra = 1
+apple = 2
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}
+
+#[test]
+fn resolver() {
+    let header = "[package]\nname = \"resolved\"\nversion = \"0.1.0\"\nedition = \"2018\"\n";
+    for tables in &["resolver = \"2\"\n", "\n[workspace]\nresolver = \"2\"\n"] {
+        let manifest = format!("{}{}", header, tables);
+        let dir = fixture(&[("Cargo.toml", &manifest), ("src/lib.rs", "")]);
+        let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+        assert_eq!(out, manifest);
+    }
+}
+
+#[test]
+fn target_edition() {
+    let manifest = r#"[package]
+name = "editions"
+version = "0.1.0"
+edition = "2021"
+
+[lib]
+crate-type = ["cdylib"]
+path = "src/ffi.rs"
+edition = "2018"
+
+[[bin]]
+name = "editions"
+edition = "2015"
+
+[[bench]]
+name = "old"
+edition = "2018"
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/ffi.rs", ""),
+        ("src/main.rs", ""),
+        ("benches/old.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}
+
+#[test]
+fn quoted_keys() {
+    let manifest = r#"[package]
+name = "quoted"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+"rénamed" = { version = "1.0", package = "serde" }
+
+[features]
+"v1.2" = []
+"c++" = ["rénamed/derive"]
+"#;
+    let expected = r#"[package]
+name = "quoted"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+"rénamed" = { version = "1.0", package = "serde" }
+
+[features]
+"c++" = ["rénamed/derive"]
+"v1.2" = []
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn virtual_manifest() {
+    let path = Path::new("/workspace/Cargo.toml");
+    let err = match parse_manifest_from_str("[workspace]\nmembers = []\n".into(), path) {
+        Ok(_) => panic!("virtual manifest parsed"),
+        Err(err) => err,
+    };
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        err.to_string().contains("`/workspace/Cargo.toml`"),
+        "{}",
+        err
+    );
+    assert!(err.to_string().contains("no `[package]` table"), "{}", err);
+}
+
+#[test]
+fn paths_without_relative_form() {
+    assert_eq!(rel_path(Path::new("/a/b"), "/a/c/d"), "../c/d");
+    // A path that doesn't share a root with the base, like one on another
+    // drive, is kept as is.
+    assert_eq!(rel_path(Path::new("/a/b"), "c/d"), "c/d");
+}
+
+#[test]
+fn path_dependency_versions() {
+    let manifest = r#"[package]
+name = "publishable"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+any = { version = "*", path = "any" }
+local = { version = "1.2", path = "local" }
+unversioned = { path = "unversioned" }
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/lib.rs", ""),
+        (
+            "local/Cargo.toml",
+            "[package]\nname = \"local\"\nversion = \"1.2.0\"\n",
+        ),
+        ("local/src/lib.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}
+
+#[test]
+fn dependency_groups() {
+    let manifest = r#"[package]
+name = "grouped"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+anyhow = "1.0"
+log = "0.4"
+
+# Serialization.
+serde = "1.0"
+serde_json = "1.0"
+"#;
+    for preserve_dependency_groups in &[false, true] {
+        let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+        let opts = Options {
+            preserve_dependency_groups: *preserve_dependency_groups,
+            preserve_comments: PreserveComments::All,
+            ..Options::default()
+        };
+        let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+        match preserve_dependency_groups {
+            true => assert_eq!(out, manifest),
+            false => assert_eq!(
+                out,
+                manifest.replace("\n\n# Serialization.", "\n# Serialization.")
+            ),
+        }
+    }
+}
+
+#[test]
+fn inherited_package_fields() {
+    let root = r#"[workspace]
+members = ["member"]
+
+[workspace.package]
+version = "1.2.3"
+license = "MIT"
+edition = "2021"
+exclude = ["/ci"]
+"#;
+    let member = r#"[package]
+name = "member"
+exclude.workspace = true
+edition.workspace = true
+license = { workspace = true }
+version.workspace = true
+"#;
+    let expected = r#"[package]
+name = "member"
+version.workspace = true
+license.workspace = true
+exclude.workspace = true
+edition.workspace = true
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", root),
+        ("member/Cargo.toml", member),
+        ("member/src/lib.rs", ""),
+    ]);
+    let path = dir.path().join("member/Cargo.toml");
+    let out = format_twice(&path, &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn license_normalization() {
+    let cases = &[
+        ("MIT/Apache-2.0", Some("Apache-2.0 OR MIT")),
+        ("Apache-2.0/MIT", Some("Apache-2.0 OR MIT")),
+        ("MIT OR Apache-2.0", Some("Apache-2.0 OR MIT")),
+        ("Zlib OR MIT/Apache-2.0", Some("Apache-2.0 OR MIT OR Zlib")),
+        (
+            "BSD-3-Clause AND (MIT OR Apache-2.0)",
+            Some("(Apache-2.0 OR MIT) AND BSD-3-Clause"),
+        ),
+        (
+            "MIT OR GPL-2.0 WITH Classpath-exception-2.0",
+            Some("GPL-2.0 WITH Classpath-exception-2.0 OR MIT"),
+        ),
+        ("See LICENSE, please", None),
+        ("MIT OR", None),
+    ];
+    for (license, expected) in cases {
+        assert_eq!(
+            normalize_license(license).as_deref(),
+            *expected,
+            "{}",
+            license
+        );
+    }
+
+    let manifest =
+        "[package]\nname = \"licensed\"\nversion = \"0.1.0\"\nlicense = \"MIT/Apache-2.0\"\n";
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let opts = Options {
+        normalize_license: true,
+        ..Options::default()
+    };
+    let out = load_and_format(&dir.path().join("Cargo.toml"), &opts).1;
+    assert!(out.contains("license = \"Apache-2.0 OR MIT\"\n"), "{}", out);
+}
+
+#[test]
+fn windows_separators() {
+    let manifest = r#"[package]
+name = "separated"
+version = "0.1.0"
+license-file = 'licenses\LICENSE'
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/lib.rs", ""),
+        ("licenses/LICENSE", ""),
+    ]);
+    let out = load_and_format(&dir.path().join("Cargo.toml"), &Options::default()).1;
+    assert!(
+        out.contains("license-file = \"licenses/LICENSE\"\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn author_normalization() {
+    let cases = &[
+        (
+            "  Jane   Doe   < jane@example.com >",
+            Some("Jane Doe <jane@example.com>"),
+        ),
+        ("Jane Doe", Some("Jane Doe")),
+        ("<jane@example.com>", Some("<jane@example.com>")),
+        ("bad <<x>>", None),
+        ("   ", None),
+    ];
+    for (author, expected) in cases {
+        assert_eq!(normalize_author(author).as_deref(), *expected, "{}", author);
+    }
+
+    let manifest = r#"[package]
+name = "authored"
+version = "0.1.0"
+authors = ["  Jane   Doe   < jane@example.com >", "bad <<x>>"]
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let opts = Options {
+        normalize_authors: true,
+        ..Options::default()
+    };
+    let out = load_and_format(&dir.path().join("Cargo.toml"), &opts).1;
+    assert!(
+        out.contains("authors = [\"Jane Doe <jane@example.com>\", \"bad <<x>>\"]\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn dep_key_order() {
+    let manifest = r#"[package]
+name = "ordered"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+clap = { optional = true, features = ["derive"], version = "3.2", default-features = false }
+rand = { optional = true, features = ["std"], workspace = true }
+
+[workspace]
+
+[workspace.dependencies]
+rand = { default-features = false, version = "0.8" }
+"#;
+    let cases = &[
+        (
+            DepKeyOrder::Canonical,
+            [
+                r#"clap = { version = "3.2", default-features = false, features = ["derive"], optional = true }"#,
+                r#"rand = { workspace = true, features = ["std"], optional = true }"#,
+                r#"rand = { version = "0.8", default-features = false }"#,
+            ],
+        ),
+        (
+            DepKeyOrder::Alphabetical,
+            [
+                r#"clap = { default-features = false, features = ["derive"], optional = true, version = "3.2" }"#,
+                r#"rand = { features = ["std"], optional = true, workspace = true }"#,
+                r#"rand = { default-features = false, version = "0.8" }"#,
+            ],
+        ),
+    ];
+    for (dep_key_order, expected) in cases {
+        let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+        let opts = Options {
+            dep_key_order: *dep_key_order,
+            ..Options::default()
+        };
+        let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+        for line in expected {
+            assert!(out.contains(&format!("\n{}\n", line)), "{}", out);
+        }
+    }
+}
+
+#[test]
+fn expanded_dependencies() {
+    let manifest = r#"[package]
+name = "expanded"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+log = "0.4"
+forked = { git = "https://github.com/example/a-repository-with-a-long-name", rev = "0123456789abcdef", features = ["one", "two"] }
+short = { git = "https://github.com/example/short" }
+"#;
+    let expected = r#"[package]
+name = "expanded"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+log = "0.4"
+short = { git = "https://github.com/example/short" }
+
+[dependencies.forked]
+git = "https://github.com/example/a-repository-with-a-long-name"
+rev = "0123456789abcdef"
+features = ["one", "two"]
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn custom_target_names_and_paths() {
+    let manifest = r#"[package]
+name = "tool"
+version = "0.1.0"
+edition = "2018"
+
+[[bin]]
+name = "tool-cli"
+path = "src/bin/tool.rs"
+
+[[bin]]
+name = "tool"
+path = "src/main.rs"
+
+[[bin]]
+name = "helper"
+
+[[test]]
+name = "integration"
+path = "tests/custom.rs"
+"#;
+    // `src/bin/tool.rs` exists too, so cargo can't infer the path of the
+    // `tool` bin.
+    let expected = r#"[package]
+name = "tool"
+version = "0.1.0"
+edition = "2018"
+
+[[bin]]
+name = "tool-cli"
+path = "src/bin/tool.rs"
+
+[[bin]]
+name = "tool"
+path = "src/main.rs"
+
+[[test]]
+name = "integration"
+path = "tests/custom.rs"
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/main.rs", ""),
+        ("src/bin/tool.rs", ""),
+        ("src/bin/helper.rs", ""),
+        ("tests/custom.rs", ""),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn config_file() {
+    let opts = Options {
+        max_width: 72,
+        preserve_inferred: true,
+        ..Options::default()
+    };
+    let dir = fixture(&[(CONFIG_FILE, &opts.config_file())]);
+    let mut read = Options::default();
+    read.read_config_file(dir.path()).unwrap();
+    assert_eq!(read.max_width, 72);
+    assert!(read.preserve_inferred);
+    assert_eq!(read.config_file(), opts.config_file());
+}
+
+#[test]
+fn only_dependencies() {
+    let manifest = r#"[package]
+version = "0.1.0"
+name = "partial"
+edition = "2018"
+
+# Pulled in for the CLI.
+[dependencies]
+serde = "1.0"
+log = { version = "0.4" }
+
+[features]
+# Everything.
+full = ["foo"]
+default  =  []
+
+[target.'cfg(unix)'.dependencies]
+libc = "0.2"
+
+# A dependency with its own table.
+[dependencies.foo]
+version = "1.0"
+optional = true
+
+[profile.release]
+lto = true # Smaller binaries.
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let opts = Options {
+        only: Some(Section::Dependencies),
+        ..Options::default()
+    };
+    let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+    let expected = r#"[package]
+version = "0.1.0"
+name = "partial"
+edition = "2018"
+
+# Pulled in for the CLI.
+[dependencies]
+# A dependency with its own table.
+foo = { version = "1.0", optional = true }
+log = "0.4"
+serde = "1.0"
+
+[target."cfg(unix)".dependencies]
+libc = "0.2"
+
+[features]
+# Everything.
+full = ["foo"]
+default  =  []
+
+[profile.release]
+lto = true # Smaller binaries.
+"#;
+    assert_eq!(out, expected);
+    // Everything outside the dependency tables is left byte for byte.
+    for section in &[
+        "[package]\nversion = \"0.1.0\"\nname = \"partial\"\nedition = \"2018\"\n",
+        "[features]\n# Everything.\nfull = [\"foo\"]\ndefault  =  []\n",
+        "[profile.release]\nlto = true # Smaller binaries.\n",
+    ] {
+        assert!(
+            manifest.contains(section) && out.contains(section),
+            "{}",
+            section
+        );
+    }
+}
+
+#[test]
+fn renamed_optional_dependency() {
+    let manifest = r#"[package]
+name = "renamed"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+alias = { package = "serde", version = "1.0", optional = true }
+
+[features]
+ser = ["dep:alias"]
+"#;
+    let expected = r#"[package]
+name = "renamed"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+alias = { version = "1.0", package = "serde", optional = true }
+
+[features]
+ser = ["dep:alias"]
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, expected);
+}
+
+#[test]
+fn feature_groups() {
+    let manifest = r#"[package]
+name = "grouped"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+serde = { version = "1.0", optional = true }
+
+# Features are additive.
+[features]
+default = ["std"]
+std = []
+
+# ---- Serialization ----
+
+wire = ["dep:serde"]
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}
+
+#[test]
+fn publish_warnings() {
+    let cases = &[
+        (
+            "",
+            &[
+                "package `published` can be published but has no `description`",
+                "package `published` can be published but has no `license` or `license-file`",
+            ][..],
+        ),
+        ("publish = false\n", &[][..]),
+    ];
+    for (publish, expected) in cases {
+        let manifest = format!(
+            "[package]\nname = \"published\"\nversion = \"0.1.0\"\n{}",
+            publish
+        );
+        let dir = fixture(&[("Cargo.toml", &manifest), ("src/lib.rs", "")]);
+        let opts = Options {
+            warn_publish: true,
+            ..Options::default()
+        };
+        let warnings = load_and_format(&dir.path().join("Cargo.toml"), &opts).2;
+        assert_eq!(warnings, *expected);
+    }
+}
+
+#[test]
+fn long_feature_lists() {
+    let manifest = r#"[package]
+name = "wide"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+log = { version = "0.4", optional = true }
+serde = { version = "1.0", optional = true }
+
+[features]
+full = ["alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliet", "kilo", "lima", "mike", "dep:serde", "log?/std"]
+alpha = []
+bravo = []
+charlie = []
+delta = []
+echo = []
+foxtrot = []
+golf = []
+hotel = []
+india = []
+juliet = []
+kilo = []
+lima = []
+mike = []
+"#;
+    let dir = fixture(&[("Cargo.toml", manifest), ("src/lib.rs", "")]);
+    let opts = Options::default();
+    let out = format_twice(&dir.path().join("Cargo.toml"), &opts);
+    let expected = r#"
+full = [
+    "alpha",
+    "bravo",
+    "charlie",
+    "delta",
+    "echo",
+    "foxtrot",
+    "golf",
+    "hotel",
+    "india",
+    "juliet",
+    "kilo",
+    "lima",
+    "mike",
+    "dep:serde",
+    "log?/std",
+]
+"#;
+    assert!(out.contains(expected), "{}", out);
+    assert!(
+        out.lines().all(|line| line.len() <= opts.max_width),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn optional_build_dependency() {
+    let manifest = r#"[package]
+name = "built"
+version = "0.1.0"
+edition = "2021"
+
+[build-dependencies]
+cc = { version = "1.0", optional = true }
+
+[features]
+native = ["dep:cc"]
+"#;
+    let dir = fixture(&[
+        ("Cargo.toml", manifest),
+        ("src/lib.rs", ""),
+        ("build.rs", "fn main() {}"),
+    ]);
+    let out = format_twice(&dir.path().join("Cargo.toml"), &Options::default());
+    assert_eq!(out, manifest);
+}